@@ -0,0 +1,819 @@
+use crate::memory_map::*;
+
+// the dmg runs at 4.194304MHz
+pub const CLOCK_SPEED: u32 = 4_194_304;
+
+// the frame sequencer steps at 512Hz
+const FRAME_SEQUENCER_CYCLES: u32 = CLOCK_SPEED / 512;
+
+// what each register reads back as on top of its value, unused and write-only bits read as 1
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10-NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf, // unused, NR21-NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30-NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+// the waveforms the square channels can play, one bit per eighth of a period
+const DUTY_CYCLES: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// the noise channel's base divisors, picked by the lower 3 bits of NR43
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// how much of the dc offset the output capacitor keeps each cycle
+const CHARGE_FACTOR: f64 = 0.999958;
+
+// the four sound channels, for muting or pulling out on their own
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+pub const CHANNELS: [Channel; 4] = [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise];
+
+impl Channel {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Square1 => "square1",
+            Channel::Square2 => "square2",
+            Channel::Wave => "wave",
+            Channel::Noise => "noise",
+        }
+    }
+}
+
+// counts down, silencing its channel when it runs out if enabled
+struct Length {
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new() -> Length {
+        Length {
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    // returns false once the channel should be switched off
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+
+        true
+    }
+}
+
+// fades a channel's volume up or down every few frame sequencer steps
+struct Envelope {
+    // NRx2 as last written
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    // with the initial volume at 0 and decreasing, the channel's dac is off
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xf8 != 0
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x7
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            if self.register & 0x8 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x8 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// channels 1 and 2
+struct Square {
+    enabled: bool,
+    duty: u8,
+    // which eighth of the waveform is playing
+    position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Square {
+    fn new() -> Square {
+        Square {
+            enabled: false,
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        if self.length.counter == 0 {
+            self.length.counter = 64;
+        }
+        self.timer = self.period();
+        self.envelope.trigger();
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 8;
+        }
+
+        self.timer -= cycles;
+    }
+
+    // the digital output, 0-15
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY_CYCLES[self.duty as usize] >> (7 - self.position) & 0x1 != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+// channel 1's frequency sweep
+struct Sweep {
+    // NR10 as last written
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+        }
+    }
+
+    // a period of 0 is treated as 8
+    fn period(&self) -> u8 {
+        match (self.register >> 4) & 0x7 {
+            0 => 8,
+            period => period,
+        }
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x7
+    }
+
+    // the next frequency, which may be past 2047 and switch the channel off
+    fn next_frequency(&self) -> u16 {
+        let change = self.shadow >> self.shift();
+
+        if self.register & 0x8 != 0 {
+            self.shadow - change
+        } else {
+            self.shadow + change
+        }
+    }
+
+    fn trigger(&mut self, channel: &mut Square) {
+        self.shadow = channel.frequency;
+        self.timer = self.period();
+        self.enabled = (self.register >> 4) & 0x7 != 0 || self.shift() != 0;
+
+        if self.shift() != 0 && self.next_frequency() > 2047 {
+            channel.enabled = false;
+        }
+    }
+
+    fn clock(&mut self, channel: &mut Square) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer != 0 {
+            return;
+        }
+
+        self.timer = self.period();
+
+        if !self.enabled || (self.register >> 4) & 0x7 == 0 {
+            return;
+        }
+
+        let frequency = self.next_frequency();
+        if frequency > 2047 {
+            channel.enabled = false;
+        } else if self.shift() != 0 {
+            self.shadow = frequency;
+            channel.frequency = frequency;
+
+            // the new frequency is checked for overflow again straight away
+            if self.next_frequency() > 2047 {
+                channel.enabled = false;
+            }
+        }
+    }
+}
+
+// channel 3, which plays back 32 4-bit samples from wave ram
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    // 0 is silent, then 100%, 50% and 25%
+    volume_code: u8,
+    position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    ram: [u8; 0x10],
+}
+
+impl Wave {
+    fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(),
+            ram: [0; 0x10],
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        if self.length.counter == 0 {
+            self.length.counter = 256;
+        }
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+
+        // the upper nibble of each byte plays first
+        let byte = self.ram[(self.position / 2) as usize];
+        let sample = if self.position & 0x1 == 0 { byte >> 4 } else { byte & 0xf };
+
+        sample >> (self.volume_code - 1)
+    }
+}
+
+// channel 4, pseudo random noise from a linear feedback shift register
+struct Noise {
+    enabled: bool,
+    // NR43 as last written
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            enabled: false,
+            register: 0,
+            lfsr: 0x7fff,
+            timer: 0,
+            length: Length::new(),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[(self.register & 0x7) as usize] << (self.register >> 4)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        if self.length.counter == 0 {
+            self.length.counter = 64;
+        }
+        self.timer = self.period();
+        self.lfsr = 0x7fff;
+        self.envelope.trigger();
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            // the xor of the lowest two bits is shifted in at the top,
+            // and also into bit 6 in 7 bit mode for a shorter, more tonal pattern
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.register & 0x8 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+// the audio processing unit, 0xff10-0xff3f
+//
+// every channel produces a 4 bit level which its dac turns into -1.0 to 1.0,
+// these are panned by NR51, scaled by NR50, and averaged down to the sample rate
+pub struct Apu {
+    powered: bool,
+    // NR10-NR52 as last written, for reading back
+    registers: [u8; 0x17],
+    channel1: Square,
+    sweep: Sweep,
+    channel2: Square,
+    channel3: Wave,
+    channel4: Noise,
+    frame_sequencer_clock: u32,
+    frame_sequencer_step: u8,
+    // samples per second, or none to not produce any
+    sample_rate: Option<u32>,
+    // counts up by the sample rate every cycle, a sample is due each time it passes the clock speed
+    sample_clock: u32,
+    // each channel's output summed since the last sample, and over how many cycles
+    accumulated: [f32; 4],
+    accumulated_cycles: u32,
+    // the dc level the output capacitors have charged to, left then right
+    capacitors: [f32; 2],
+    // interleaved left and right samples, waiting to be taken
+    samples: Vec<f32>,
+    // channels left out of the mix
+    muted: [bool; 4],
+    // when any channel is soloed, only soloed channels are mixed
+    soloed: [bool; 4],
+    // each channel on its own in mono, before panning, volume and muting, if wanted
+    stems: Option<[Vec<f32>; 4]>,
+    stem_capacitors: [f32; 4],
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            powered: false,
+            registers: [0; 0x17],
+            channel1: Square::new(),
+            sweep: Sweep::new(),
+            channel2: Square::new(),
+            channel3: Wave::new(),
+            channel4: Noise::new(),
+            frame_sequencer_clock: 0,
+            frame_sequencer_step: 0,
+            sample_rate: None,
+            sample_clock: 0,
+            accumulated: [0.0; 4],
+            accumulated_cycles: 0,
+            capacitors: [0.0; 2],
+            samples: Vec::new(),
+            muted: [false; 4],
+            soloed: [false; 4],
+            stems: None,
+            stem_capacitors: [0.0; 4],
+        }
+    }
+
+    // start producing stereo samples at the given rate, or stop with none
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.sample_rate = sample_rate;
+        self.sample_clock = 0;
+        self.accumulated = [0.0; 4];
+        self.accumulated_cycles = 0;
+    }
+
+    // the samples produced since this was last called, interleaved left then right
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel.index()] = soloed;
+    }
+
+    // whether the channel makes it into the mixed output
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        let i = channel.index();
+
+        if self.soloed.iter().any(|&soloed| soloed) {
+            self.soloed[i]
+        } else {
+            !self.muted[i]
+        }
+    }
+
+    // also produce a separate mono stream for each channel, at the same rate as the mix
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.stems = if enabled { Some(Default::default()) } else { None };
+    }
+
+    // each channel's samples produced since this was last called, in the order of CHANNELS
+    pub fn take_stems(&mut self) -> [Vec<f32>; 4] {
+        match &mut self.stems {
+            Some(stems) => std::mem::take(stems),
+            None => Default::default(),
+        }
+    }
+
+    // advance the apu by the number of cycles the cpu just took
+    pub fn step(&mut self, cycles: u8) {
+        // like the timer, work in m-cycles
+        for _ in 0..cycles / 4 {
+            if self.powered {
+                self.frame_sequencer_clock += 4;
+                if self.frame_sequencer_clock >= FRAME_SEQUENCER_CYCLES {
+                    self.frame_sequencer_clock -= FRAME_SEQUENCER_CYCLES;
+                    self.step_frame_sequencer();
+                }
+
+                self.channel1.tick(4);
+                self.channel2.tick(4);
+                self.channel3.tick(4);
+                self.channel4.tick(4);
+            }
+
+            if let Some(sample_rate) = self.sample_rate {
+                let outputs = self.channel_outputs();
+                for (sum, output) in self.accumulated.iter_mut().zip(outputs.iter()) {
+                    *sum += output * 4.0;
+                }
+                self.accumulated_cycles += 4;
+
+                self.sample_clock += sample_rate * 4;
+                if self.sample_clock >= CLOCK_SPEED {
+                    self.sample_clock -= CLOCK_SPEED;
+                    self.push_sample();
+                }
+            }
+        }
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            NR52_REGISTER => {
+                let channels = [
+                    self.channel1.enabled,
+                    self.channel2.enabled,
+                    self.channel3.enabled,
+                    self.channel4.enabled,
+                ];
+
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |status, (i, &enabled)| status | (enabled as u8) << i);
+
+                (self.powered as u8) << 7 | 0x70 | status
+            }
+            NR10_REGISTER..=NR51_REGISTER => {
+                let i = (address - NR10_REGISTER) as usize;
+                self.registers[i] | READ_MASKS[i]
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self.channel3.ram[(address - WAVE_RAM_START) as usize],
+            _ => 0xff,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, new_byte: u8) {
+        // wave ram can be written with the power off, nothing else but NR52 can
+        if let WAVE_RAM_START..=WAVE_RAM_END = address {
+            self.channel3.ram[(address - WAVE_RAM_START) as usize] = new_byte;
+            return;
+        }
+
+        if address == NR52_REGISTER {
+            self.set_power(new_byte & 0x80 != 0);
+            return;
+        }
+
+        if !self.powered || !(NR10_REGISTER..=NR51_REGISTER).contains(&address) {
+            return;
+        }
+
+        self.registers[(address - NR10_REGISTER) as usize] = new_byte;
+
+        match address {
+            NR10_REGISTER => self.sweep.register = new_byte,
+            NR11_REGISTER => {
+                self.channel1.duty = new_byte >> 6;
+                self.channel1.length.counter = 64 - (new_byte & 0x3f) as u16;
+            }
+            NR12_REGISTER => {
+                self.channel1.envelope.register = new_byte;
+                self.channel1.enabled &= self.channel1.envelope.dac_enabled();
+            }
+            NR13_REGISTER => self.channel1.frequency = (self.channel1.frequency & 0x700) | new_byte as u16,
+            NR14_REGISTER => {
+                self.channel1.frequency = (self.channel1.frequency & 0xff) | ((new_byte & 0x7) as u16) << 8;
+                self.channel1.length.enabled = new_byte & 0x40 != 0;
+                if new_byte & 0x80 != 0 {
+                    self.channel1.trigger();
+                    self.sweep.trigger(&mut self.channel1);
+                }
+            }
+            NR21_REGISTER => {
+                self.channel2.duty = new_byte >> 6;
+                self.channel2.length.counter = 64 - (new_byte & 0x3f) as u16;
+            }
+            NR22_REGISTER => {
+                self.channel2.envelope.register = new_byte;
+                self.channel2.enabled &= self.channel2.envelope.dac_enabled();
+            }
+            NR23_REGISTER => self.channel2.frequency = (self.channel2.frequency & 0x700) | new_byte as u16,
+            NR24_REGISTER => {
+                self.channel2.frequency = (self.channel2.frequency & 0xff) | ((new_byte & 0x7) as u16) << 8;
+                self.channel2.length.enabled = new_byte & 0x40 != 0;
+                if new_byte & 0x80 != 0 {
+                    self.channel2.trigger();
+                }
+            }
+            NR30_REGISTER => {
+                self.channel3.dac_enabled = new_byte & 0x80 != 0;
+                self.channel3.enabled &= self.channel3.dac_enabled;
+            }
+            NR31_REGISTER => self.channel3.length.counter = 256 - new_byte as u16,
+            NR32_REGISTER => self.channel3.volume_code = (new_byte >> 5) & 0x3,
+            NR33_REGISTER => self.channel3.frequency = (self.channel3.frequency & 0x700) | new_byte as u16,
+            NR34_REGISTER => {
+                self.channel3.frequency = (self.channel3.frequency & 0xff) | ((new_byte & 0x7) as u16) << 8;
+                self.channel3.length.enabled = new_byte & 0x40 != 0;
+                if new_byte & 0x80 != 0 {
+                    self.channel3.trigger();
+                }
+            }
+            NR41_REGISTER => self.channel4.length.counter = 64 - (new_byte & 0x3f) as u16,
+            NR42_REGISTER => {
+                self.channel4.envelope.register = new_byte;
+                self.channel4.enabled &= self.channel4.envelope.dac_enabled();
+            }
+            NR43_REGISTER => self.channel4.register = new_byte,
+            NR44_REGISTER => {
+                self.channel4.length.enabled = new_byte & 0x40 != 0;
+                if new_byte & 0x80 != 0 {
+                    self.channel4.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // load the state the boot rom leaves the apu in, without triggering anything: its chime has
+    // faded out on channel 1, which is still on at volume 0, and the other channels never played
+    pub fn skip_boot(&mut self) {
+        self.set_power(true);
+
+        let registers = [
+            (NR10_REGISTER, 0x80),
+            (NR11_REGISTER, 0xbf),
+            (NR12_REGISTER, 0xf3),
+            (NR13_REGISTER, 0xff),
+            (NR14_REGISTER, 0xbf),
+            (NR21_REGISTER, 0x3f),
+            (NR22_REGISTER, 0x00),
+            (NR23_REGISTER, 0xff),
+            (NR24_REGISTER, 0xbf),
+            (NR30_REGISTER, 0x7f),
+            (NR31_REGISTER, 0xff),
+            (NR32_REGISTER, 0x9f),
+            (NR33_REGISTER, 0xff),
+            (NR34_REGISTER, 0xbf),
+            (NR41_REGISTER, 0xff),
+            (NR42_REGISTER, 0x00),
+            (NR43_REGISTER, 0x00),
+            (NR44_REGISTER, 0xbf),
+            (NR50_REGISTER, 0x77),
+            (NR51_REGISTER, 0xf3),
+        ];
+
+        for &(address, value) in registers.iter() {
+            // the trigger bit is write only, so leaving it out only changes what happens now
+            let value = match address {
+                NR14_REGISTER | NR24_REGISTER | NR34_REGISTER | NR44_REGISTER => value & 0x7f,
+                _ => value,
+            };
+            self.write_register(address, value);
+        }
+
+        self.channel1.enabled = true;
+    }
+
+    // switching the apu off clears every register, and they can't be written until it's back on
+    fn set_power(&mut self, powered: bool) {
+        if powered && !self.powered {
+            self.frame_sequencer_clock = 0;
+            self.frame_sequencer_step = 0;
+            self.channel1.position = 0;
+            self.channel2.position = 0;
+            self.channel3.position = 0;
+        } else if !powered && self.powered {
+            // wave ram survives
+            let ram = self.channel3.ram;
+
+            self.registers = [0; 0x17];
+            self.channel1 = Square::new();
+            self.sweep = Sweep::new();
+            self.channel2 = Square::new();
+            self.channel3 = Wave::new();
+            self.channel4 = Noise::new();
+            self.channel3.ram = ram;
+        }
+
+        self.powered = powered;
+    }
+
+    // lengths are clocked on every other step, the sweep every fourth, and envelopes on the last
+    fn step_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+
+        if step & 0x1 == 0 {
+            self.channel1.enabled &= self.channel1.length.clock();
+            self.channel2.enabled &= self.channel2.length.clock();
+            self.channel3.enabled &= self.channel3.length.clock();
+            self.channel4.enabled &= self.channel4.length.clock();
+        }
+
+        if step == 2 || step == 6 {
+            self.sweep.clock(&mut self.channel1);
+        }
+
+        if step == 7 {
+            self.channel1.envelope.clock();
+            self.channel2.envelope.clock();
+            self.channel4.envelope.clock();
+        }
+    }
+
+    // what each channel's dac is putting out right now, from -1.0 to 1.0
+    //
+    // a dac that's off puts out nothing at all
+    fn channel_outputs(&self) -> [f32; 4] {
+        let dac = |enabled: bool, output: u8| {
+            if enabled {
+                output as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+
+        [
+            dac(self.channel1.envelope.dac_enabled(), self.channel1.output()),
+            dac(self.channel2.envelope.dac_enabled(), self.channel2.output()),
+            dac(self.channel3.dac_enabled, self.channel3.output()),
+            dac(self.channel4.envelope.dac_enabled(), self.channel4.output()),
+        ]
+    }
+
+    // average the channels over the last sample period and mix them down to stereo
+    fn push_sample(&mut self) {
+        let cycles = self.accumulated_cycles.max(1) as f32;
+        let channels: Vec<f32> = self.accumulated.iter().map(|sum| sum / cycles).collect();
+        self.accumulated = [0.0; 4];
+        self.accumulated_cycles = 0;
+
+        let nr50 = self.registers[(NR50_REGISTER - NR10_REGISTER) as usize];
+        let nr51 = self.registers[(NR51_REGISTER - NR10_REGISTER) as usize];
+
+        // NR51 has a bit per channel for the right in its lower nibble, and the left in its upper
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, &channel) in channels.iter().enumerate() {
+            if !self.is_audible(CHANNELS[i]) {
+                continue;
+            }
+
+            if nr51 & (0x10 << i) != 0 {
+                left += channel;
+            }
+            if nr51 & (0x01 << i) != 0 {
+                right += channel;
+            }
+        }
+
+        // each side's master volume goes from 1/8 up to full
+        let left = left / 4.0 * (((nr50 >> 4) & 0x7) + 1) as f32 / 8.0;
+        let right = right / 4.0 * ((nr50 & 0x7) + 1) as f32 / 8.0;
+
+        // the capacitors slowly charge to the dc offset, which is taken out of the output
+        let sample_rate = self.sample_rate.unwrap_or(CLOCK_SPEED);
+        let charge = CHARGE_FACTOR.powf(CLOCK_SPEED as f64 / sample_rate as f64) as f32;
+
+        for (i, &level) in [left, right].iter().enumerate() {
+            let output = if self.powered { level - self.capacitors[i] } else { 0.0 };
+            self.capacitors[i] = level - output * charge;
+            self.samples.push(output);
+        }
+
+        if let Some(stems) = &mut self.stems {
+            for (i, &level) in channels.iter().enumerate() {
+                let output = if self.powered { level - self.stem_capacitors[i] } else { 0.0 };
+                self.stem_capacitors[i] = level - output * charge;
+                stems[i].push(output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipping_the_boot_rom_leaves_channel_1_on_but_silent() {
+        let mut apu = Apu::new();
+        apu.skip_boot();
+
+        assert_eq!(apu.read_register(NR52_REGISTER), 0xf1);
+        assert_eq!(apu.read_register(NR12_REGISTER), 0xf3);
+        assert_eq!(apu.channel1.envelope.volume, 0);
+
+        // nothing else was triggered either, so a trigger is the only thing that starts channel 2
+        apu.write_register(NR22_REGISTER, 0xf0);
+        assert_eq!(apu.read_register(NR52_REGISTER), 0xf1);
+        apu.write_register(NR24_REGISTER, 0x80);
+        assert_eq!(apu.read_register(NR52_REGISTER), 0xf3);
+    }
+}
@@ -0,0 +1,313 @@
+use std::io;
+
+use crate::header::CartridgeHeader;
+use crate::rtc::{Clock, Rtc, SystemClock, RTC_DAY_HIGH, RTC_SECONDS};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// mbc2 has 512 half-byte cells of ram built into the controller
+const MBC2_RAM_SIZE: usize = 0x200;
+
+// the memory bank controller on the cartridge, which decides
+// which rom and ram banks are visible to the cpu
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+impl Mbc {
+    // pick the controller from the cartridge type byte in the header
+    pub fn from_cartridge_type(byte: u8) -> Option<Mbc> {
+        match byte {
+            0x00 | 0x08 | 0x09 => Some(Mbc::None),
+            0x01..=0x03 => Some(Mbc::Mbc1),
+            0x05 | 0x06 => Some(Mbc::Mbc2),
+            0x0f..=0x13 => Some(Mbc::Mbc3),
+            0x19..=0x1e => Some(Mbc::Mbc5),
+            _ => None,
+        }
+    }
+}
+
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Mbc,
+    // whether the ram is kept alive by a battery, and should be saved
+    has_battery: bool,
+    // set whenever battery backed ram is written, so it's only saved when it changes
+    ram_modified: bool,
+    // mbc3 carts with a timer have a clock, which is saved after the ram
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    // the bank mapped into 0x4000-0x7fff, as last written by the game
+    rom_bank: u16,
+    // the ram bank, which mbc1 also uses for the upper bits of the rom bank
+    ram_bank: u8,
+    // mbc1 only, whether the 2 bit register applies to 0x0000-0x3fff and ram as well
+    advanced_banking: bool,
+}
+
+impl Cartridge {
+    // fails if the game uses a cartridge type that isn't supported
+    pub fn new(game: Vec<u8>) -> io::Result<Cartridge> {
+        let mut rom = game;
+
+        // always have at least the two banks the cpu can see at once
+        if rom.len() < 2 * ROM_BANK_SIZE {
+            rom.resize(2 * ROM_BANK_SIZE, 0xff);
+        }
+
+        // the rom is at least 0x8000 bytes now, so this shouldn't ever fail
+        let header = CartridgeHeader::parse(&rom)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "rom is too short to have a header"))?;
+
+        let mbc = Mbc::from_cartridge_type(header.cartridge_type).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported cartridge type 0x{:02x}", header.cartridge_type),
+            )
+        })?;
+
+        let ram_size = match mbc {
+            Mbc::Mbc2 => MBC2_RAM_SIZE,
+            _ => header.ram_size(),
+        };
+
+        Ok(Cartridge {
+            rom,
+            ram: vec![0; ram_size],
+            mbc,
+            has_battery: header.has_battery(),
+            ram_modified: false,
+            rtc: if header.has_rtc() {
+                Some(Rtc::new(Box::new(SystemClock)))
+            } else {
+                None
+            },
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            advanced_banking: false,
+        })
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    // change where the rtc gets the time from, if the cartridge has one
+    pub fn set_rtc_clock(&mut self, clock: Box<dyn Clock>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    // the contents of battery backed ram, in the raw format other emulators use for .sav files,
+    // followed by the clock state for carts with an rtc
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.has_battery {
+            return None;
+        }
+
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.save_data());
+        }
+
+        Some(data)
+    }
+
+    // restore battery backed ram from a .sav file
+    //
+    // files of the wrong size are loaded as far as they go rather than rejected,
+    // since some emulators pad or truncate them
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_save_data(&data[len..]);
+        }
+    }
+
+    // whether the save data has changed since the last call
+    pub fn take_modified(&mut self) -> bool {
+        let modified = self.ram_modified;
+        self.ram_modified = false;
+        modified
+    }
+
+    // read from 0x0000-0x7fff
+    pub fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < ROM_BANK_SIZE as u16 {
+            self.low_rom_bank()
+        } else {
+            self.high_rom_bank()
+        };
+
+        let offset = bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+        self.rom[offset]
+    }
+
+    // writes to 0x0000-0x7fff don't change the rom, they control the mbc
+    pub fn write_rom(&mut self, address: u16, new_byte: u8) {
+        match self.mbc {
+            Mbc::None => {}
+            Mbc::Mbc1 => match address {
+                0x0000..=0x1fff => self.ram_enabled = new_byte & 0xf == 0xa,
+                0x2000..=0x3fff => {
+                    // bank 0 can't be selected here, it maps to bank 1 instead
+                    let bank = new_byte & 0x1f;
+                    self.rom_bank = if bank == 0 { 1 } else { bank as u16 };
+                }
+                0x4000..=0x5fff => self.ram_bank = new_byte & 0x3,
+                _ => self.advanced_banking = new_byte & 0x1 != 0,
+            },
+            Mbc::Mbc2 => {
+                // bit 8 of the address picks between ram enable and rom bank select
+                if address < 0x4000 {
+                    if address & 0x100 == 0 {
+                        self.ram_enabled = new_byte & 0xf == 0xa;
+                    } else {
+                        let bank = new_byte & 0xf;
+                        self.rom_bank = if bank == 0 { 1 } else { bank as u16 };
+                    }
+                }
+            }
+            Mbc::Mbc3 => match address {
+                0x0000..=0x1fff => self.ram_enabled = new_byte & 0xf == 0xa,
+                0x2000..=0x3fff => {
+                    let bank = new_byte & 0x7f;
+                    self.rom_bank = if bank == 0 { 1 } else { bank as u16 };
+                }
+                0x4000..=0x5fff => self.ram_bank = new_byte,
+                _ => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write_latch(new_byte);
+                    }
+                }
+            },
+            Mbc::Mbc5 => match address {
+                0x0000..=0x1fff => self.ram_enabled = new_byte & 0xf == 0xa,
+                // the rom bank is 9 bits split across two registers, and bank 0 is allowed
+                0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | new_byte as u16,
+                0x3000..=0x3fff => {
+                    self.rom_bank = (self.rom_bank & 0xff) | ((new_byte as u16 & 0x1) << 8)
+                }
+                0x4000..=0x5fff => self.ram_bank = new_byte & 0xf,
+                _ => {}
+            },
+        }
+    }
+
+    // read from 0xa000-0xbfff
+    pub fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled && self.mbc != Mbc::None {
+            return 0xff;
+        }
+
+        if let Some(rtc) = self.selected_rtc() {
+            return rtc.read(self.ram_bank);
+        }
+
+        match self.ram_offset(address) {
+            // mbc2 ram is only 4 bits wide, the upper bits read as 1
+            Some(offset) if self.mbc == Mbc::Mbc2 => self.ram[offset] | 0xf0,
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    // write to 0xa000-0xbfff
+    pub fn write_ram(&mut self, address: u16, new_byte: u8) {
+        if !self.ram_enabled && self.mbc != Mbc::None {
+            return;
+        }
+
+        let register = self.ram_bank;
+        if let Some(rtc) = self.selected_rtc_mut() {
+            rtc.write(register, new_byte);
+            self.ram_modified |= self.has_battery;
+            return;
+        }
+
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = if self.mbc == Mbc::Mbc2 {
+                new_byte & 0xf
+            } else {
+                new_byte
+            };
+
+            self.ram_modified |= self.has_battery;
+        }
+    }
+
+    // the rtc, if one of its registers is mapped into 0xa000-0xbfff instead of ram
+    fn selected_rtc(&self) -> Option<&Rtc> {
+        match self.ram_bank {
+            RTC_SECONDS..=RTC_DAY_HIGH if self.mbc == Mbc::Mbc3 => self.rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn selected_rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self.ram_bank {
+            RTC_SECONDS..=RTC_DAY_HIGH if self.mbc == Mbc::Mbc3 => self.rtc.as_mut(),
+            _ => None,
+        }
+    }
+
+    // the bank mapped into 0x0000-0x3fff
+    fn low_rom_bank(&self) -> usize {
+        let bank = match self.mbc {
+            // in advanced banking mode mbc1 also switches the lower area,
+            // which large roms use to reach banks 0x20, 0x40 and 0x60
+            Mbc::Mbc1 if self.advanced_banking => (self.ram_bank as usize) << 5,
+            _ => 0,
+        };
+
+        bank % self.rom_bank_count()
+    }
+
+    // the bank mapped into 0x4000-0x7fff
+    fn high_rom_bank(&self) -> usize {
+        let bank = match self.mbc {
+            Mbc::None => 1,
+            Mbc::Mbc1 => ((self.ram_bank as usize) << 5) | self.rom_bank as usize,
+            _ => self.rom_bank as usize,
+        };
+
+        bank % self.rom_bank_count()
+    }
+
+    // where in the ram buffer an address in 0xa000-0xbfff points, if anywhere
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let address = (address & 0x1fff) as usize;
+
+        let offset = match self.mbc {
+            // only 512 cells, which repeat through the whole area
+            Mbc::Mbc2 => address & (MBC2_RAM_SIZE - 1),
+            Mbc::Mbc1 if self.advanced_banking => self.ram_bank as usize * RAM_BANK_SIZE + address,
+            Mbc::Mbc1 | Mbc::None => address,
+            // mbc3 banks past 0x03 select the rtc rather than ram
+            Mbc::Mbc3 if self.ram_bank > 0x03 => return None,
+            Mbc::Mbc3 | Mbc::Mbc5 => self.ram_bank as usize * RAM_BANK_SIZE + address,
+        };
+
+        Some(offset % self.ram.len())
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / ROM_BANK_SIZE
+    }
+}
@@ -1,29 +1,144 @@
+use std::io;
+
 use crate::instructions::*;
 use crate::memory_bus::MemoryBus;
 use crate::registers::Registers;
 
+// the dmg runs at 4194304 Hz and draws ~59.7 frames a second,
+// so each frame takes exactly 70224 cycles (154 lines * 456 cycles)
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+// the hardware revision being emulated, which only changes
+// the state the boot rom leaves behind
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    // original game boy
+    Dmg,
+    // game boy pocket
+    Mgb,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pc: u16,
     sp: u16,
     registers: Registers,
     bus: MemoryBus,
-    interrupts: bool,
+    // the interrupt master enable flag (IME)
+    ime: bool,
+    // EI only takes effect after the instruction following it
+    ime_scheduled: bool,
     is_halted: bool,
+    // cycles run past the end of the last frame, carried into the next one
+    frame_cycles: u32,
 }
 
 impl CPU {
-    pub fn new(rom: Vec<u8>, game: Vec<u8>) -> CPU {
-        CPU {
+    pub fn new(rom: Vec<u8>, game: Vec<u8>) -> io::Result<CPU> {
+        Ok(CPU {
             pc: 0,
             sp: 0,
             registers: Registers::new(),
-            bus: MemoryBus::new(rom, game),
-            interrupts: true,
-            is_halted: false
+            bus: MemoryBus::new(rom, game)?,
+            ime: false,
+            ime_scheduled: false,
+            is_halted: false,
+            frame_cycles: 0,
+        })
+    }
+
+    // put the cpu and hardware into the state the boot rom leaves them in,
+    // so a game can be started at 0x100 without a bios
+    pub fn skip_boot(&mut self, model: Model) {
+        // the only way to tell a pocket from an original is A after boot
+        self.registers.a = match model {
+            Model::Dmg => 0x01,
+            Model::Mgb => 0xff,
+        };
+
+        // the boot rom leaves the flags from its header checksum check behind,
+        // so half carry and carry depend on the checksum byte
+        let checksum_nonzero = self.bus.read_byte(0x14d) != 0;
+        self.registers.f.set(Some(true), Some(false), Some(checksum_nonzero), Some(checksum_nonzero));
+
+        self.registers.set_bc(0x0013);
+        self.registers.set_de(0x00d8);
+        self.registers.set_hl(0x014d);
+        self.sp = 0xfffe;
+        self.pc = 0x100;
+
+        self.bus.skip_boot();
+    }
+
+    // execute a single instruction, tick the rest of the hardware alongside it,
+    // and return the number of cycles it took
+    pub fn step(&mut self) -> u8 {
+        // if EI was the last instruction, IME is turned on once this step's instruction is done
+        let enable_ime = self.ime_scheduled;
+
+        let cycles = if let Some(cycles) = self.handle_interrupts() {
+            cycles
+        } else if self.is_halted {
+            4
+        } else {
+            self.fetch_execute()
+        };
+
+        // a DI straight after EI cancels it
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+
+        self.bus.step(cycles);
+
+        cycles
+    }
+
+    // run until exactly one frame's worth of cycles has elapsed
+    //
+    // instructions don't line up with the end of a frame, so any cycles that
+    // spill over are counted towards the next frame
+    pub fn run_frame(&mut self) {
+        while self.frame_cycles < CYCLES_PER_FRAME {
+            self.frame_cycles += self.step() as u32;
         }
+
+        self.frame_cycles -= CYCLES_PER_FRAME;
+    }
+
+    pub fn bus(&self) -> &MemoryBus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut MemoryBus {
+        &mut self.bus
+    }
+
+    // wake up from HALT and service the highest priority pending interrupt, if there is one
+    //
+    // returns the number of cycles taken if an interrupt was dispatched
+    fn handle_interrupts(&mut self) -> Option<u8> {
+        let interrupt = self.bus.pending_interrupt()?;
+
+        // any pending interrupt ends a HALT, even if IME is off
+        self.is_halted = false;
+
+        if !self.ime {
+            return None;
+        }
+
+        // disable interrupts while the handler runs, acknowledge the request,
+        // then call the handler (2 wait states, 2 pushes and the jump itself)
+        self.ime = false;
+        self.bus.clear_interrupt(interrupt);
+        self.push(self.pc);
+        self.pc = interrupt.vector();
+
+        Some(20)
     }
 
-    fn fetch_execute(&mut self) {
+    fn fetch_execute(&mut self) -> u8 {
         let mut instr_byte = self.bus.read_byte(self.pc);
         let is_prefixed = instr_byte == 0xcb;
 
@@ -32,7 +147,7 @@ impl CPU {
             instr_byte = self.read_next_byte();
         }
 
-        let (next_pc, _cycles) = if let Some(instr) = Instruction::disassemble(instr_byte, is_prefixed) {
+        let (next_pc, cycles) = if let Some(instr) = Instruction::disassemble(instr_byte, is_prefixed) {
             self.execute(instr)
         } else {
             panic!(
@@ -43,6 +158,8 @@ impl CPU {
         };
 
         self.pc = next_pc;
+
+        cycles
     }
 
     fn execute(&mut self, instr: Instruction) -> (u16, u8) {
@@ -57,9 +174,9 @@ impl CPU {
                 }
             }
             Instruction::ADC(target) => {
-                // do an ADD, then add the carry
-                self.add(target);
-                self.add_a(self.registers.f.carry as u8);
+                // add the value and the carry together so the flags cover both
+                let value = self.get_register_from_arith(target);
+                self.registers.a = self.adc_a(value);
 
                 match target {
                     ArithTarget::D8 => (self.pc.wrapping_add(2), 8),
@@ -82,7 +199,8 @@ impl CPU {
                 let value = ((self.read_next_byte() as i8) as i16) as u16;
                 let result = self.sp.wrapping_add(value);
 
-                self.registers.f.set(Some(false), Some(false), Some((self.sp * 0xf) + (value & 0xf) > 0xf), Some((self.sp & 0xff) + (value & 0xff) > 0xff));
+                self.registers.f.set(Some(false), Some(false), Some((self.sp & 0xf) + (value & 0xf) > 0xf), Some((self.sp & 0xff) + (value & 0xff) > 0xff));
+                self.sp = result;
                 (self.pc.wrapping_add(2), 16)
             }
             Instruction::SUB(target) => {
@@ -94,9 +212,9 @@ impl CPU {
                 }
             }
             Instruction::SBC(target) => {
-                // do a SUB, then add the carry
-                self.sub(target);
-                self.sub_a(self.registers.f.carry as u8);
+                // subtract the value and the carry together so the flags cover both
+                let value = self.get_register_from_arith(target);
+                self.registers.a = self.sbc_a(value);
                 match target {
                     ArithTarget::D8 => (self.pc.wrapping_add(2), 8),
                     ArithTarget::HLI => (self.pc.wrapping_add(1), 8),
@@ -207,7 +325,7 @@ impl CPU {
                 */
                 self.registers
                     .f
-                    .set(Some(new_carry != 0), Some(false), Some(false), Some(false));
+                    .set(Some(false), Some(false), Some(false), Some(new_carry != 0));
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::RLA => {
@@ -230,7 +348,7 @@ impl CPU {
 
                 self.registers
                     .f
-                    .set(Some(new_carry != 0), Some(false), Some(false), Some(false));
+                    .set(Some(false), Some(false), Some(false), Some(new_carry != 0));
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::RRCA => {
@@ -252,7 +370,7 @@ impl CPU {
 
                 self.registers
                     .f
-                    .set(Some(new_carry != 0), Some(false), Some(false), Some(false));
+                    .set(Some(false), Some(false), Some(false), Some(new_carry != 0));
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::RLCA => {
@@ -274,7 +392,7 @@ impl CPU {
 
                 self.registers
                     .f
-                    .set(Some(new_carry != 0), Some(false), Some(false), Some(false));
+                    .set(Some(false), Some(false), Some(false), Some(new_carry != 0));
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::CPL => {
@@ -390,7 +508,7 @@ impl CPU {
                     Some((value & 0x1) != 0),
                 );
 
-                self.set_register_from_prefix(target, value);
+                self.set_register_from_prefix(target, result);
 
                 match target {
                     PrefixTarget::HLI => (self.pc.wrapping_add(2), 16),
@@ -428,7 +546,7 @@ impl CPU {
                     Some(new_carry != 0),
                 );
 
-                self.set_register_from_prefix(target, value);
+                self.set_register_from_prefix(target, result);
 
                 match target {
                     PrefixTarget::HLI => (self.pc.wrapping_add(2), 16),
@@ -461,7 +579,7 @@ impl CPU {
                     Some(new_carry != 0),
                 );
 
-                self.set_register_from_prefix(target, value);
+                self.set_register_from_prefix(target, result);
 
                 match target {
                     PrefixTarget::HLI => (self.pc.wrapping_add(2), 16),
@@ -662,7 +780,7 @@ impl CPU {
                 // return 3 or the address jumped to
                 self.jr(jump_condition)
             }
-            Instruction::JPHLI => (self.bus.read_byte(self.registers.get_hl()) as u16, 4),
+            Instruction::JPHLI => (self.registers.get_hl(), 4),
             Instruction::LD(load_type) => {
                 match load_type {
                     LoadType::Byte(target, source) => {
@@ -703,16 +821,16 @@ impl CPU {
                             LoadIndirectTarget::BCI => self.bus.read_byte(self.registers.get_bc()),
                             LoadIndirectTarget::DEI => self.bus.read_byte(self.registers.get_de()),
                             LoadIndirectTarget::HLIPLUS => {
-                                // increment hl, then get the byte at address hl
-                                self.registers
-                                    .set_hl(self.registers.get_hl().wrapping_add(1));
-                                self.bus.read_byte(self.registers.get_hl())
+                                // get the byte at address hl, then increment hl
+                                let hl = self.registers.get_hl();
+                                self.registers.set_hl(hl.wrapping_add(1));
+                                self.bus.read_byte(hl)
                             }
                             LoadIndirectTarget::HLIMINUS => {
-                                // decrement hl, then get the byte at address hl
-                                self.registers
-                                    .set_hl(self.registers.get_hl().wrapping_sub(1));
-                                self.bus.read_byte(self.registers.get_hl())
+                                // get the byte at address hl, then decrement hl
+                                let hl = self.registers.get_hl();
+                                self.registers.set_hl(hl.wrapping_sub(1));
+                                self.bus.read_byte(hl)
                             }
                             LoadIndirectTarget::WORDI => self.bus.read_byte(self.read_next_word()),
                             LoadIndirectTarget::CI => {
@@ -736,16 +854,16 @@ impl CPU {
                                 self.bus.set_byte(self.registers.get_de(), self.registers.a)
                             }
                             LoadIndirectTarget::HLIPLUS => {
-                                // increment hl, then get the byte at address hl
-                                self.registers
-                                    .set_hl(self.registers.get_hl().wrapping_add(1));
-                                self.bus.set_byte(self.registers.get_hl(), self.registers.a);
+                                // set the byte at address hl, then increment hl
+                                let hl = self.registers.get_hl();
+                                self.bus.set_byte(hl, self.registers.a);
+                                self.registers.set_hl(hl.wrapping_add(1));
                             }
                             LoadIndirectTarget::HLIMINUS => {
-                                // decrement hl, then get the byte at address hl
-                                self.registers
-                                    .set_hl(self.registers.get_hl().wrapping_sub(1));
-                                self.bus.set_byte(self.registers.get_hl(), self.registers.a);
+                                // set the byte at address hl, then decrement hl
+                                let hl = self.registers.get_hl();
+                                self.bus.set_byte(hl, self.registers.a);
+                                self.registers.set_hl(hl.wrapping_sub(1));
                             }
                             LoadIndirectTarget::WORDI => {
                                 self.bus.set_byte(self.read_next_word(), self.registers.a)
@@ -778,7 +896,7 @@ impl CPU {
                         let value = ((self.read_next_byte() as i8) as i16) as u16;
                         let result = self.sp.wrapping_add(value);
 
-                        self.registers.f.set(Some(false), Some(false), Some((self.sp * 0xf) + (value & 0xf) > 0xf), Some((self.sp & 0xff) + (value & 0xff) > 0xff));
+                        self.registers.f.set(Some(false), Some(false), Some((self.sp & 0xf) + (value & 0xf) > 0xf), Some((self.sp & 0xff) + (value & 0xff) > 0xff));
                         self.registers.set_hl(result);
                         (self.pc.wrapping_add(2), 12)
                     }
@@ -788,7 +906,7 @@ impl CPU {
                     }
                     LoadType::IndirectFromSP => {
                         self.bus.set_byte(self.read_next_word(), (self.sp & 0xff) as u8);
-                        self.bus.set_byte(self.read_next_word() + 1, ((self.sp & 0xff00) >> 8) as u8);
+                        self.bus.set_byte(self.read_next_word().wrapping_add(1), ((self.sp & 0xff00) >> 8) as u8);
                         (self.pc.wrapping_add(3), 20)
                     }
                 }
@@ -871,16 +989,17 @@ impl CPU {
                     RstTarget::X38 => 0x38,
                 };
 
-                (pc, 24)
+                (pc, 16)
             }
             Instruction::RETI => {
-                self.interrupts = true;
+                // unlike EI, RETI turns interrupts back on immediately
+                self.ime = true;
                 (self.pop(), 16)
             }
             Instruction::NOP => (self.pc.wrapping_add(1), 4),
             Instruction::HALT => {
                 self.is_halted = true;
-                (self.pc.wrapping_add(1), 16)
+                (self.pc.wrapping_add(1), 4)
             }
             Instruction::DAA => {
                 let value = self.registers.a;
@@ -905,22 +1024,29 @@ impl CPU {
                     } else {
                         value.wrapping_add(0xa0)
                     }
+                } else if self.registers.f.half_carry {
+                    value.wrapping_add(0xfa)
                 } else {
                     value
                 };
 
+                self.registers.a = result;
                 self.registers.f.set(Some(result == 0), None, Some(false), Some(carry));
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::STOP => {
-                panic!("STOP instruction!")
+                // treated like HALT, sleeping until an interrupt such as a button press comes in,
+                // and skipping the padding byte that always follows it
+                self.is_halted = true;
+                (self.pc.wrapping_add(2), 4)
             }
             Instruction::DI => {
-                self.interrupts = false;
+                self.ime = false;
+                self.ime_scheduled = false;
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::EI => {
-                self.interrupts = true;
+                self.ime_scheduled = true;
                 (self.pc.wrapping_add(1), 4)
             }
         }
@@ -970,16 +1096,16 @@ impl CPU {
     fn inc(&mut self, target: IncDecTarget) {
         match target {
             IncDecTarget::BC => {
-                self.registers.set_bc(self.registers.get_bc() + 1);
+                self.registers.set_bc(self.registers.get_bc().wrapping_add(1));
             }
             IncDecTarget::DE => {
-                self.registers.set_de(self.registers.get_de() + 1);
+                self.registers.set_de(self.registers.get_de().wrapping_add(1));
             }
             IncDecTarget::HL => {
-                self.registers.set_hl(self.registers.get_hl() + 1);
+                self.registers.set_hl(self.registers.get_hl().wrapping_add(1));
             }
             IncDecTarget::SP => {
-                self.sp += 1;
+                self.sp = self.sp.wrapping_add(1);
             }
             IncDecTarget::A => {
                 // let (result, _) = self.registers.a.overflowing_add(1);
@@ -1135,16 +1261,16 @@ impl CPU {
     fn dec(&mut self, target: IncDecTarget) {
         match target {
             IncDecTarget::BC => {
-                self.registers.set_bc(self.registers.get_bc() - 1);
+                self.registers.set_bc(self.registers.get_bc().wrapping_sub(1));
             }
             IncDecTarget::DE => {
-                self.registers.set_de(self.registers.get_de() - 1);
+                self.registers.set_de(self.registers.get_de().wrapping_sub(1));
             }
             IncDecTarget::HL => {
-                self.registers.set_hl(self.registers.get_hl() - 1);
+                self.registers.set_hl(self.registers.get_hl().wrapping_sub(1));
             }
             IncDecTarget::SP => {
-                self.sp -= 1;
+                self.sp = self.sp.wrapping_sub(1);
             }
             IncDecTarget::A => {
                 let result = self.registers.a.wrapping_sub(1);
@@ -1407,7 +1533,7 @@ impl CPU {
             next_pc = if offset >= 0 {
                 next_pc.wrapping_add(offset as u16)
             } else {
-                next_pc.wrapping_sub(offset.abs() as u16)
+                next_pc.wrapping_sub(offset.unsigned_abs() as u16)
             };
 
             (next_pc, 16)
@@ -1560,6 +1686,21 @@ impl CPU {
         result
     }
 
+    // add to register a along with the carry flag, and set flags accordingly
+    fn adc_a(&mut self, value: u8) -> u8 {
+        let carry = self.registers.f.carry as u8;
+        let result = self.registers.a.wrapping_add(value).wrapping_add(carry);
+
+        self.registers.f.set(
+            Some(result == 0),
+            Some(false),
+            Some((self.registers.a & 0xf) + (value & 0xf) + carry > 0xf),
+            Some(self.registers.a as u16 + value as u16 + carry as u16 > 0xff),
+        );
+
+        result
+    }
+
     // add to registers hl and set flags accordingly
     fn add_hl(&mut self, value: u16) -> u16 {
         let (result, did_overflow) = self.registers.get_hl().overflowing_add(value);
@@ -1616,6 +1757,21 @@ impl CPU {
         result
     }
 
+    // sub from a along with the carry flag, set flags accordingly
+    fn sbc_a(&mut self, value: u8) -> u8 {
+        let carry = self.registers.f.carry as u8;
+        let result = self.registers.a.wrapping_sub(value).wrapping_sub(carry);
+
+        self.registers.f.set(
+            Some(result == 0),
+            Some(true),
+            Some((self.registers.a & 0xf) < (value & 0xf) + carry),
+            Some((self.registers.a as u16) < value as u16 + carry as u16),
+        );
+
+        result
+    }
+
     // push the value onto the stack (i.e. push word)
     //
     // the stack is full descending, so it grows "down" in memory
@@ -1644,3 +1800,43 @@ impl CPU {
         (upper_byte << 8) | lower_byte
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::{test_emulator, Emulator};
+
+    #[test]
+    fn hl_increment_and_decrement_loads_use_hl_before_changing_it() {
+        let code = [
+            0x21, 0x00, 0xc0, // ld hl, 0xc000
+            0x3e, 0x11, // ld a, 0x11
+            0x22, // ld (hl+), a
+            0x3e, 0x22, // ld a, 0x22
+            0x32, // ld (hl-), a
+            0x2a, // ld a, (hl+)
+            0x3a, // ld a, (hl-)
+        ];
+        let mut emulator = test_emulator(0x00, &code);
+        let hl = |emulator: &Emulator| emulator.cpu().registers.get_hl();
+
+        for _ in 0..3 {
+            emulator.step();
+        }
+        assert_eq!(emulator.cpu().bus().read_byte(0xc000), 0x11);
+        assert_eq!(hl(&emulator), 0xc001);
+
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.cpu().bus().read_byte(0xc001), 0x22);
+        assert_eq!(emulator.cpu().bus().read_byte(0xc002), 0x00);
+        assert_eq!(hl(&emulator), 0xc000);
+
+        emulator.step();
+        assert_eq!(emulator.cpu().registers.a, 0x11);
+        assert_eq!(hl(&emulator), 0xc001);
+
+        emulator.step();
+        assert_eq!(emulator.cpu().registers.a, 0x22);
+        assert_eq!(hl(&emulator), 0xc000);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+use crate::apu::Channel;
+use crate::cpu::{Model, CPU};
+use crate::gbs::Gbs;
+use crate::joypad::Button;
+use crate::rtc::Clock;
+use crate::serial::SerialDevice;
+
+// the public face of the crate
+//
+// front ends should only need this to load a game, run it,
+// read back what's on screen and feed in button presses
+pub struct Emulator {
+    cpu: CPU,
+}
+
+impl Emulator {
+    // fails if the game can't be run, e.g. because its cartridge type isn't supported
+    pub fn new(bios: Vec<u8>, game: Vec<u8>) -> io::Result<Emulator> {
+        Ok(Emulator {
+            cpu: CPU::new(bios, game)?,
+        })
+    }
+
+    // start the game straight from 0x100, in the state the boot rom of the given model leaves behind
+    pub fn without_bios(game: Vec<u8>, model: Model) -> io::Result<Emulator> {
+        let mut cpu = CPU::new(Vec::new(), game)?;
+        cpu.skip_boot(model);
+
+        Ok(Emulator { cpu })
+    }
+
+    // play a song from a gbs rip, counting from 0
+    pub fn from_gbs(gbs: &Gbs, song: u8) -> io::Result<Emulator> {
+        Emulator::without_bios(gbs.rom(song), Model::Dmg)
+    }
+
+    // execute a single instruction, returning the number of cycles it took
+    pub fn step(&mut self) -> u8 {
+        self.cpu.step()
+    }
+
+    // run until a whole frame has been drawn
+    pub fn run_frame(&mut self) {
+        self.cpu.run_frame();
+    }
+
+    // the current frame, one shade (0 for white through 3 for black) per pixel,
+    // stored row by row
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.bus().gpu.framebuffer()
+    }
+
+    // press or release a button, which the game sees through P1 and the joypad interrupt
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.bus_mut().joypad.set_button(button, pressed);
+    }
+
+    // plug a device into the link port, in place of the default capture of sent bytes
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.bus_mut().serial.set_device(device);
+    }
+
+    // unplug the device from the link port, e.g. to move it over to a fresh emulator on reset
+    pub fn take_serial_device(&mut self) -> Box<dyn SerialDevice> {
+        self.cpu.bus_mut().serial.take_device()
+    }
+
+    // start producing audio at the given number of stereo samples per second
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus_mut().apu.set_sample_rate(Some(sample_rate));
+    }
+
+    // the audio produced since this was last called, interleaved left then right, from -1.0 to 1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus_mut().apu.take_samples()
+    }
+
+    // leave a channel out of the mixed audio
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.cpu.bus_mut().apu.set_muted(channel, muted);
+    }
+
+    // while any channel is soloed, only soloed channels are heard
+    pub fn set_channel_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.cpu.bus_mut().apu.set_soloed(channel, soloed);
+    }
+
+    // also produce each channel's audio on its own, unaffected by muting
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.cpu.bus_mut().apu.set_stems_enabled(enabled);
+    }
+
+    // each channel's mono audio since this was last called, in the order of CHANNELS
+    pub fn take_stems(&mut self) -> [Vec<f32>; 4] {
+        self.cpu.bus_mut().apu.take_stems()
+    }
+
+    // whether the cartridge has battery backed ram that should be saved
+    pub fn has_battery(&self) -> bool {
+        self.cpu.bus().cartridge.has_battery()
+    }
+
+    // the battery backed ram, as it would be stored in a .sav file
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.cpu.bus().cartridge.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.cpu.bus_mut().cartridge.load_save_data(data);
+    }
+
+    // whether the save data has changed since this was last called
+    pub fn save_data_modified(&mut self) -> bool {
+        self.cpu.bus_mut().cartridge.take_modified()
+    }
+
+    // replace the wall clock the cartridge's rtc runs from, e.g. with a fake one in tests
+    pub fn set_rtc_clock(&mut self, clock: Box<dyn Clock>) {
+        self.cpu.bus_mut().cartridge.set_rtc_clock(clock);
+    }
+
+    // the internals, for tests that check what a program did
+    #[cfg(test)]
+    pub(crate) fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+}
+
+// a cartridge of the given type that runs the given code from 0x100, for tests
+#[cfg(test)]
+pub(crate) fn test_emulator(cartridge_type: u8, code: &[u8]) -> Emulator {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = cartridge_type;
+    rom[0x100..0x100 + code.len()].copy_from_slice(code);
+
+    Emulator::without_bios(rom, Model::Dmg).unwrap()
+}
@@ -25,24 +25,20 @@ impl Flags {
         half_carry: Option<bool>,
         carry: Option<bool>,
     ) {
-        match zero {
-            Some(z) => self.zero = z,
-            None => {}
+        if let Some(z) = zero {
+            self.zero = z;
         }
 
-        match subtract {
-            Some(s) => self.subtract = s,
-            None => {}
+        if let Some(s) = subtract {
+            self.subtract = s;
         }
 
-        match half_carry {
-            Some(h) => self.half_carry = h,
-            None => {}
+        if let Some(h) = half_carry {
+            self.half_carry = h;
         }
 
-        match carry {
-            Some(c) => self.carry = c,
-            None => {}
+        if let Some(c) = carry {
+            self.carry = c;
         }
     }
 }
@@ -0,0 +1,64 @@
+// the pieces every front end needs, whatever it draws to
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::apu::CLOCK_SPEED;
+use crate::cpu::CYCLES_PER_FRAME;
+use crate::joypad::Button;
+
+// the four shades of the dmg's green screen, lightest first, as red, green and blue
+pub const PALETTE: [(u8, u8, u8); 4] = [
+    (0xe0, 0xf8, 0xd0),
+    (0x88, 0xc0, 0x70),
+    (0x34, 0x68, 0x56),
+    (0x08, 0x18, 0x20),
+];
+
+// every button, for front ends that work out the state of them all each frame
+pub const BUTTONS: [Button; 8] = [
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+];
+
+// how far behind the pacing can get before it gives up catching up
+const MAX_LAG_FRAMES: u32 = 4;
+
+// keeps a front end showing frames at the real ~59.7Hz
+pub struct FramePacer {
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new() -> FramePacer {
+        FramePacer {
+            next_frame: Instant::now(),
+        }
+    }
+
+    // sleep until it's time for the next frame, running the given number of times faster than real
+    pub fn wait(&mut self, speed: u32) {
+        let frame = Duration::from_nanos(CYCLES_PER_FRAME as u64 * 1_000_000_000 / CLOCK_SPEED as u64) / speed;
+        self.next_frame += frame;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame * MAX_LAG_FRAMES {
+            // too slow to keep up, so stop trying to make up the lost time
+            self.next_frame = now;
+        }
+    }
+}
+
+impl Default for FramePacer {
+    fn default() -> FramePacer {
+        FramePacer::new()
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::header::ascii_string;
+
+// a .gbs file is this header followed by the sound driver and music data
+const HEADER_SIZE: usize = 0x70;
+
+const MAGIC: &[u8] = b"GBS";
+
+const VERSION: usize = 0x03;
+const SONG_COUNT: usize = 0x04;
+const FIRST_SONG: usize = 0x05;
+const LOAD_ADDRESS: usize = 0x06;
+const INIT_ADDRESS: usize = 0x08;
+const PLAY_ADDRESS: usize = 0x0a;
+const STACK_POINTER: usize = 0x0c;
+const TIMER_MODULO: usize = 0x0e;
+const TIMER_CONTROL: usize = 0x0f;
+const TITLE: usize = 0x10;
+const AUTHOR: usize = 0x30;
+const COPYRIGHT: usize = 0x50;
+
+// everything below this is left to the player, for the restart and interrupt vectors
+const MIN_LOAD_ADDRESS: u16 = 0x400;
+
+// where the player's own code goes, just past the cartridge header
+const DRIVER_START: usize = 0x150;
+
+// the rom is an mbc5 cart with ram, which the music data can bank switch around
+const CARTRIDGE_TYPE: u8 = 0x1a;
+const RAM_SIZE_CODE: u8 = 0x03;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    // 1 based, unlike the song passed to init
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    // returns none if the file isn't a gbs file, or is too short to contain a header
+    pub fn parse(file: &[u8]) -> Option<GbsHeader> {
+        if file.len() < HEADER_SIZE || &file[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+
+        let word = |offset: usize| file[offset] as u16 | (file[offset + 1] as u16) << 8;
+
+        Some(GbsHeader {
+            version: file[VERSION],
+            song_count: file[SONG_COUNT],
+            first_song: file[FIRST_SONG],
+            load_address: word(LOAD_ADDRESS),
+            init_address: word(INIT_ADDRESS),
+            play_address: word(PLAY_ADDRESS),
+            stack_pointer: word(STACK_POINTER),
+            timer_modulo: file[TIMER_MODULO],
+            timer_control: file[TIMER_CONTROL],
+            title: ascii_string(&file[TITLE..AUTHOR]),
+            author: ascii_string(&file[AUTHOR..COPYRIGHT]),
+            copyright: ascii_string(&file[COPYRIGHT..HEADER_SIZE]),
+        })
+    }
+
+    // play is called on the timer interrupt if TAC's enable bit is set, otherwise on vblank
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+}
+
+impl fmt::Display for GbsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "title:      {}", self.title)?;
+        writeln!(f, "author:     {}", self.author)?;
+        writeln!(f, "copyright:  {}", self.copyright)?;
+        writeln!(f, "songs:      {} (first {})", self.song_count, self.first_song)?;
+        writeln!(
+            f,
+            "addresses:  load 0x{:04x}, init 0x{:04x}, play 0x{:04x}, stack 0x{:04x}",
+            self.load_address, self.init_address, self.play_address, self.stack_pointer
+        )?;
+        write!(
+            f,
+            "play rate:  {}",
+            if self.uses_timer() {
+                format!("timer (TMA 0x{:02x}, TAC 0x{:02x})", self.timer_modulo, self.timer_control)
+            } else {
+                "vblank".to_string()
+            }
+        )
+    }
+}
+
+// a game boy sound system rip, the music from a game without the game
+//
+// it's played by building a cartridge around the music data with a tiny driver that
+// calls init with the song number, then calls play from the timer or vblank interrupt
+pub struct Gbs {
+    pub header: GbsHeader,
+    data: Vec<u8>,
+}
+
+impl Gbs {
+    // returns none if the file isn't a gbs file that can be played
+    pub fn parse(file: &[u8]) -> Option<Gbs> {
+        let header = GbsHeader::parse(file)?;
+
+        if header.load_address < MIN_LOAD_ADDRESS || header.load_address >= 0x8000 {
+            return None;
+        }
+
+        Some(Gbs {
+            header,
+            data: file[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    // a cartridge rom that plays the given song, counting from 0, when started at 0x100
+    pub fn rom(&self, song: u8) -> Vec<u8> {
+        let header = &self.header;
+        let load = header.load_address as usize;
+
+        // the music data goes at the load address, rounded up to whole banks
+        let size = load + self.data.len();
+        let banks = size.div_ceil(ROM_BANK_SIZE).max(2);
+        let mut rom = vec![0xff; banks * ROM_BANK_SIZE];
+        rom[load..size].copy_from_slice(&self.data);
+
+        // the restart vectors jump to the same place past the load address
+        for vector in (0x00..0x40).step_by(8) {
+            write_code(&mut rom, vector, &jump(0xc3, header.load_address + vector as u16));
+        }
+
+        // the interrupt vectors, only vblank or the timer is ever enabled
+        for vector in (0x40..=0x60).step_by(8) {
+            write_code(&mut rom, vector, &[0xd9]);
+        }
+        let play = [jump(0xcd, header.play_address).as_ref(), &[0xd9]].concat();
+        write_code(&mut rom, 0x40, &play);
+        write_code(&mut rom, 0x50, &play);
+
+        // the entry point skips over the cartridge header to the driver
+        write_code(&mut rom, 0x100, &[0x00, 0xc3, DRIVER_START as u8, (DRIVER_START >> 8) as u8]);
+        rom[0x147] = CARTRIDGE_TYPE;
+        rom[0x149] = RAM_SIZE_CODE;
+
+        let (timer_control, interrupts) = if header.uses_timer() {
+            (header.timer_control & 0x07, 0x04)
+        } else {
+            (0x00, 0x01)
+        };
+
+        let driver = [
+            &[0xf3][..],                                // di
+            &jump(0x31, header.stack_pointer),          // ld sp, stack pointer
+            &[0x3e, 0x0a, 0xea, 0x00, 0x00],            // enable cartridge ram
+            &[0x3e, header.timer_modulo, 0xe0, 0x06],   // TMA
+            &[0x3e, timer_control, 0xe0, 0x07],         // TAC
+            &[0x3e, song],                              // ld a, song
+            &jump(0xcd, header.init_address),           // call init
+            &[0xaf, 0xe0, 0x0f],                        // clear IF
+            &[0x3e, interrupts, 0xe0, 0xff],            // IE
+            &[0xfb],                                    // ei
+            &[0x76, 0x18, 0xfd],                        // halt, then back to halt after each play
+        ]
+        .concat();
+        write_code(&mut rom, DRIVER_START, &driver);
+
+        rom
+    }
+}
+
+// an instruction with a 16 bit operand, like jp, call or ld sp
+fn jump(opcode: u8, address: u16) -> [u8; 3] {
+    [opcode, address as u8, (address >> 8) as u8]
+}
+
+fn write_code(rom: &mut [u8], address: usize, code: &[u8]) {
+    rom[address..address + code.len()].copy_from_slice(code);
+}
@@ -1,4 +1,7 @@
-#[derive(Copy, Clone)]
+use crate::interrupts::Interrupt;
+use crate::memory_map::*;
+
+#[derive(Copy, Clone, PartialEq)]
 enum TilePixelValue {
     Zero,
     One,
@@ -8,6 +11,29 @@ enum TilePixelValue {
 
 type Tile = [[TilePixelValue; 8]; 8];
 
+// tile data takes up 0x8000-0x97ff, followed by the two 32x32 tile maps
+const TILE_SET_END: usize = 0x1800;
+const TILE_MAP_0: usize = 0x1800;
+const TILE_MAP_1: usize = 0x1c00;
+
+// the hardware only draws the first 10 sprites it finds on each line
+const SPRITES_PER_LINE: usize = 10;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// each scanline takes 456 cycles, and there are 154 lines per frame
+// (144 visible lines followed by 10 lines of vblank)
+const CYCLES_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+
+// how long each mode of a visible line lasts, adding up to a whole line
+const OAM_CYCLES: u32 = 80;
+const TRANSFER_CYCLES: u32 = 172;
+const HBLANK_CYCLES: u32 = 204;
+
+// the value of each mode is what shows up in the lower 2 bits of STAT
+#[derive(Copy, Clone, PartialEq)]
 enum Mode {
     HBlank,
     VBlank,
@@ -15,10 +41,22 @@ enum Mode {
     Transfer
 }
 
+#[allow(non_snake_case)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct GPU {
+    // cycles spent in the current mode
+    clock: u32,
+    // whether the lcd was on last step, so we know when it gets switched back on
+    lcd_on: bool,
+    // the STAT interrupt only fires when this goes from low to high
+    stat_line: bool,
+    // the window keeps its own line counter, which only advances on lines it was drawn on
+    window_line: u8,
     vram: [u8; 0x2000],
     oam: [u8; 0xa0],
     tile_set: [Tile; 384],
+    // one shade per pixel of the screen, stored row by row
+    framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
     pub LCDC: u8,
     pub STAT: u8,
     pub SCY: u8,
@@ -35,9 +73,14 @@ pub struct GPU {
 impl GPU {
     pub fn new() -> GPU {
         GPU {
+            clock: 0,
+            lcd_on: false,
+            stat_line: false,
+            window_line: 0,
             vram: [0; 0x2000],
             oam: [0; 0xa0],
             tile_set: [[[TilePixelValue::Zero; 8]; 8]; 384],
+            framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             LCDC: 0,
             STAT: 0,
             SCY: 0,
@@ -52,8 +95,161 @@ impl GPU {
         }
     }
 
+    // advance the gpu by the number of cycles the cpu just took
+    //
+    // each visible line goes through oam scan -> pixel transfer -> hblank,
+    // then lines 144-153 are spent in vblank
+    //
+    // returns the interrupts requested along the way, in the same layout as IF
+    pub fn step(&mut self, cycles: u8) -> u8 {
+        if !self.display_enabled() {
+            // with the lcd off, LY is stuck at 0 and STAT reports hblank
+            self.lcd_on = false;
+            self.clock = 0;
+            self.LY = 0;
+            self.set_mode(Mode::HBlank);
+            self.stat_line = false;
+            return 0;
+        }
+
+        if !self.lcd_on {
+            // switching the lcd on starts again from the top of the screen
+            self.lcd_on = true;
+            self.window_line = 0;
+            self.set_mode(Mode::Oam);
+        }
+
+        let mut interrupts = 0;
+        self.clock += cycles as u32;
+
+        loop {
+            let mode = self.get_mode();
+
+            let mode_cycles = match mode {
+                Mode::Oam => OAM_CYCLES,
+                Mode::Transfer => TRANSFER_CYCLES,
+                Mode::HBlank => HBLANK_CYCLES,
+                Mode::VBlank => CYCLES_PER_LINE,
+            };
+
+            if self.clock < mode_cycles {
+                break;
+            }
+
+            self.clock -= mode_cycles;
+
+            match mode {
+                Mode::Oam => self.set_mode(Mode::Transfer),
+                Mode::Transfer => {
+                    self.render_scanline();
+                    self.set_mode(Mode::HBlank);
+                }
+                Mode::HBlank => {
+                    self.LY += 1;
+
+                    if self.LY as usize == SCREEN_HEIGHT {
+                        self.set_mode(Mode::VBlank);
+                        interrupts |= Interrupt::VBlank.mask();
+                    } else {
+                        self.set_mode(Mode::Oam);
+                    }
+                }
+                Mode::VBlank => {
+                    self.LY = (self.LY + 1) % LINES_PER_FRAME;
+
+                    if self.LY == 0 {
+                        self.window_line = 0;
+                        self.set_mode(Mode::Oam);
+                    }
+                }
+            }
+
+            interrupts |= self.update_stat();
+        }
+
+        interrupts | self.update_stat()
+    }
+
+    // read one of the lcd registers at 0xff40-0xff4b
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            LCDC_REGISTER => self.LCDC,
+            // bit 7 doesn't exist and reads as 1
+            STAT_REGISTER => self.STAT | 0x80,
+            SCY_REGISTER => self.SCY,
+            SCX_REGISTER => self.SCX,
+            LY_REGISTER => self.LY,
+            LYC_REGISTER => self.LYC,
+            BGP_REGISTER => self.BGP,
+            OBP0_REGISTER => self.OBP0,
+            OBP1_REGISTER => self.OBP1,
+            WY_REGISTER => self.WY,
+            WX_REGISTER => self.WX,
+            _ => 0xff,
+        }
+    }
+
+    // write one of the lcd registers at 0xff40-0xff4b
+    pub fn write_register(&mut self, address: u16, new_byte: u8) {
+        match address {
+            LCDC_REGISTER => self.LCDC = new_byte,
+            // the mode and coincidence bits are read only, only the interrupt sources can be written
+            STAT_REGISTER => self.STAT = (self.STAT & 0x07) | (new_byte & 0x78),
+            SCY_REGISTER => self.SCY = new_byte,
+            SCX_REGISTER => self.SCX = new_byte,
+            // LY is read only
+            LY_REGISTER => {}
+            LYC_REGISTER => self.LYC = new_byte,
+            BGP_REGISTER => self.BGP = new_byte,
+            OBP0_REGISTER => self.OBP0 = new_byte,
+            OBP1_REGISTER => self.OBP1 = new_byte,
+            WY_REGISTER => self.WY = new_byte,
+            WX_REGISTER => self.WX = new_byte,
+            _ => {}
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[address as usize]
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[address as usize]
+    }
+
     pub fn set_vram(&mut self, address: u16, new_byte: u8) {
-        self.vram[address as usize] = new_byte;
+        let index = address as usize;
+        self.vram[index] = new_byte;
+
+        // keep the decoded tile set in sync with the raw tile data
+        if index >= TILE_SET_END {
+            return;
+        }
+
+        // each row of a tile is two bytes, the first holding the low bit of
+        // every pixel and the second holding the high bit
+        let row_start = index & !0x1;
+        let low = self.vram[row_start];
+        let high = self.vram[row_start + 1];
+
+        let tile = index / 16;
+        let row = (index % 16) / 2;
+
+        for x in 0..8 {
+            let bit = 7 - x;
+            let value = (((high >> bit) & 0x1) << 1) | ((low >> bit) & 0x1);
+
+            self.tile_set[tile][row][x] = match value {
+                0 => TilePixelValue::Zero,
+                1 => TilePixelValue::One,
+                2 => TilePixelValue::Two,
+                _ => TilePixelValue::Three,
+            };
+        }
     }
 
     pub fn set_oam(&mut self, address: u16, new_byte: u8) {
@@ -65,12 +261,161 @@ impl GPU {
             0 => Mode::HBlank,
             1 => Mode::VBlank,
             2 => Mode::Oam,
-            3 => Mode::Transfer
+            _ => Mode::Transfer
         }
     }
 
     fn set_mode(&mut self, mode: Mode) {
-        self.STAT |= mode as u8;
+        self.STAT = (self.STAT & !0x3) | mode as u8;
+    }
+
+    // refresh the LY=LYC coincidence bit, and work out whether the STAT interrupt should fire
+    //
+    // every enabled source is ored onto a single line, so a new source becoming
+    // active while another already holds the line high doesn't fire again
+    fn update_stat(&mut self) -> u8 {
+        if self.LY == self.LYC {
+            self.STAT |= 0x4;
+        } else {
+            self.STAT &= !0x4;
+        }
+
+        let mode = self.get_mode();
+        let line = (self.STAT & 0x08 != 0 && mode == Mode::HBlank)
+            || (self.STAT & 0x10 != 0 && mode == Mode::VBlank)
+            || (self.STAT & 0x20 != 0 && mode == Mode::Oam)
+            || (self.STAT & 0x40 != 0 && self.STAT & 0x4 != 0);
+
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+
+        if rising {
+            Interrupt::LcdStat.mask()
+        } else {
+            0
+        }
+    }
+
+    // draw the background, window and sprites for the current line into the framebuffer
+    fn render_scanline(&mut self) {
+        let line = self.LY as usize;
+
+        // the colour number (before the palette) of each background/window pixel,
+        // which sprites need to know to work out their priority
+        let mut bg_colours = [TilePixelValue::Zero; SCREEN_WIDTH];
+
+        // on the dmg, clearing bit 0 blanks both the background and the window to white,
+        // whatever BGP says, while sprites still treat it as colour 0
+        let bg_enabled = self.LCDC & 0x01 != 0;
+        if bg_enabled {
+            let bg_map = if self.LCDC & 0x08 != 0 { TILE_MAP_1 } else { TILE_MAP_0 };
+            let y = self.SCY.wrapping_add(self.LY);
+
+            for (x, colour) in bg_colours.iter_mut().enumerate() {
+                *colour = self.tile_map_pixel(bg_map, self.SCX.wrapping_add(x as u8), y);
+            }
+
+            // the window is drawn over the background starting at (WX - 7, WY)
+            let window_x = self.WX as usize;
+            if self.LCDC & 0x20 != 0 && self.LY >= self.WY && window_x < SCREEN_WIDTH + 7 {
+                let window_map = if self.LCDC & 0x40 != 0 { TILE_MAP_1 } else { TILE_MAP_0 };
+
+                for (x, colour) in bg_colours.iter_mut().enumerate() {
+                    if x + 7 >= window_x {
+                        *colour =
+                            self.tile_map_pixel(window_map, (x + 7 - window_x) as u8, self.window_line);
+                    }
+                }
+
+                self.window_line += 1;
+            }
+        }
+
+        let row = &mut self.framebuffer[line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH];
+        for (pixel, &colour) in row.iter_mut().zip(bg_colours.iter()) {
+            *pixel = if bg_enabled { apply_palette(self.BGP, colour) } else { 0 };
+        }
+
+        if self.LCDC & 0x02 != 0 {
+            self.render_sprites(line, &bg_colours);
+        }
+    }
+
+    // the colour number of a pixel in one of the 256x256 tile maps
+    fn tile_map_pixel(&self, map: usize, x: u8, y: u8) -> TilePixelValue {
+        let tile_number = self.vram[map + (y as usize / 8) * 32 + (x as usize / 8)];
+
+        // bit 4 picks between tiles 0-255 from 0x8000, or tiles -128-127 from 0x9000
+        let tile = if self.LCDC & 0x10 != 0 {
+            tile_number as usize
+        } else {
+            (256 + (tile_number as i8) as i16) as usize
+        };
+
+        self.tile_set[tile][y as usize % 8][x as usize % 8]
+    }
+
+    fn render_sprites(&mut self, line: usize, bg_colours: &[TilePixelValue; SCREEN_WIDTH]) {
+        let height = if self.LCDC & 0x04 != 0 { 16 } else { 8 };
+
+        // oam holds 40 sprites of 4 bytes each: y + 16, x + 8, tile number, attributes
+        let mut sprites: Vec<usize> = (0..40)
+            .map(|i| i * 4)
+            .filter(|&sprite| {
+                let top = self.oam[sprite] as isize - 16;
+                (line as isize) >= top && (line as isize) < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+
+        // the sprite with the smaller x wins, then the one earlier in oam,
+        // so draw the lowest priority sprites first and let the others cover them
+        sprites.sort_by_key(|&sprite| (self.oam[sprite + 1], sprite));
+        sprites.reverse();
+
+        for sprite in sprites {
+            let top = self.oam[sprite] as isize - 16;
+            let left = self.oam[sprite + 1] as isize - 8;
+            let attributes = self.oam[sprite + 3];
+
+            let behind_bg = attributes & 0x80 != 0;
+            let y_flip = attributes & 0x40 != 0;
+            let x_flip = attributes & 0x20 != 0;
+            let palette = if attributes & 0x10 != 0 { self.OBP1 } else { self.OBP0 };
+
+            let mut row = (line as isize - top) as usize;
+            if y_flip {
+                row = height as usize - 1 - row;
+            }
+
+            // tall sprites ignore the lowest bit of the tile number, using it for the bottom half
+            let tile = if height == 16 {
+                (self.oam[sprite + 2] & 0xfe) as usize + row / 8
+            } else {
+                self.oam[sprite + 2] as usize
+            };
+
+            for column in 0..8 {
+                let x = left + column as isize;
+                if x < 0 || x >= SCREEN_WIDTH as isize {
+                    continue;
+                }
+
+                let tile_x = if x_flip { 7 - column } else { column };
+                let colour = self.tile_set[tile][row % 8][tile_x];
+
+                // colour 0 is transparent for sprites
+                if colour == TilePixelValue::Zero {
+                    continue;
+                }
+
+                if behind_bg && bg_colours[x as usize] != TilePixelValue::Zero {
+                    continue;
+                }
+
+                self.framebuffer[line * SCREEN_WIDTH + x as usize] = apply_palette(palette, colour);
+            }
+        }
     }
 
     // get the msb as a bool
@@ -78,3 +423,29 @@ impl GPU {
         ((self.LCDC & 0x80) >> 7) != 0
     }
 }
+
+// map a colour number to a shade using one of the palette registers,
+// which hold 2 bits per colour number
+fn apply_palette(palette: u8, colour: TilePixelValue) -> u8 {
+    (palette >> (colour as u8 * 2)) & 0x3
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::test_emulator;
+
+    #[test]
+    fn disabled_background_is_white_whatever_the_palette() {
+        let code = [
+            0x3e, 0xff, 0xe0, 0x47, // ld a, 0xff; ldh (BGP), a
+            0x3e, 0x80, 0xe0, 0x40, // ld a, 0x80; ldh (LCDC), a, the lcd on with everything else off
+            0x18, 0xfe, // jr -2
+        ];
+        let mut emulator = test_emulator(0x00, &code);
+
+        emulator.run_frame();
+        emulator.run_frame();
+
+        assert!(emulator.framebuffer().iter().all(|&shade| shade == 0));
+    }
+}
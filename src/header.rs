@@ -0,0 +1,260 @@
+use std::fmt;
+
+// the header lives at 0x0100-0x014f of every cartridge
+pub const HEADER_END: usize = 0x150;
+
+const LOGO_START: usize = 0x104;
+const TITLE_START: usize = 0x134;
+const MANUFACTURER_CODE_START: usize = 0x13f;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE_CODE_START: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const DESTINATION_CODE: usize = 0x14a;
+const OLD_LICENSEE_CODE: usize = 0x14b;
+const VERSION: usize = 0x14c;
+const HEADER_CHECKSUM: usize = 0x14d;
+const GLOBAL_CHECKSUM: usize = 0x14e;
+
+// the boot rom refuses to start a game unless this is at 0x0104-0x0133
+const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CgbSupport {
+    // plain dmg game
+    None,
+    // runs on both, with colour on a cgb
+    Supported,
+    // only runs on a cgb
+    Required,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub new_licensee_code: String,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub logo_valid: bool,
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    // returns none if the rom is too short to contain a header
+    pub fn parse(rom: &[u8]) -> Option<CartridgeHeader> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+
+        let cgb_support = match rom[CGB_FLAG] {
+            0xc0 => CgbSupport::Required,
+            0x80 => CgbSupport::Supported,
+            _ => CgbSupport::None,
+        };
+
+        // newer cgb-era games shortened the title to make room for a 4 character
+        // manufacturer code, older ones use the whole area for the title
+        let manufacturer_code = &rom[MANUFACTURER_CODE_START..CGB_FLAG];
+        let has_manufacturer_code = cgb_support != CgbSupport::None
+            && manufacturer_code
+                .iter()
+                .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+
+        let title_end = if has_manufacturer_code {
+            MANUFACTURER_CODE_START
+        } else if cgb_support != CgbSupport::None {
+            CGB_FLAG
+        } else {
+            NEW_LICENSEE_CODE_START
+        };
+
+        // the header checksum covers 0x0134-0x014c
+        let header_checksum = rom[TITLE_START..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        // the global checksum is every byte of the rom except the checksum itself,
+        // and unlike everything else it's stored big endian
+        let global_checksum = (rom[GLOBAL_CHECKSUM] as u16) << 8 | rom[GLOBAL_CHECKSUM + 1] as u16;
+        let global_sum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+
+        Some(CartridgeHeader {
+            title: ascii_string(&rom[TITLE_START..title_end]),
+            manufacturer_code: if has_manufacturer_code {
+                Some(ascii_string(manufacturer_code))
+            } else {
+                None
+            },
+            cgb_support,
+            new_licensee_code: ascii_string(&rom[NEW_LICENSEE_CODE_START..SGB_FLAG]),
+            sgb_support: rom[SGB_FLAG] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size_code: rom[ROM_SIZE],
+            ram_size_code: rom[RAM_SIZE],
+            destination: if rom[DESTINATION_CODE] == 0x00 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            old_licensee_code: rom[OLD_LICENSEE_CODE],
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum,
+            logo_valid: rom[LOGO_START..TITLE_START] == NINTENDO_LOGO,
+            header_checksum_valid: header_checksum == rom[HEADER_CHECKSUM],
+            global_checksum_valid: global_sum == global_checksum,
+        })
+    }
+
+    // size of the rom in bytes, if the size code is a known one
+    pub fn rom_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some(0x8000 << self.rom_size_code),
+            _ => None,
+        }
+    }
+
+    // size of the external ram in bytes
+    pub fn ram_size(&self) -> usize {
+        match self.ram_size_code {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff
+        )
+    }
+
+    // whether the cartridge has an mbc3 real time clock
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0f | 0x10)
+    }
+
+    // the licensee is stored in the old single byte code, unless
+    // that is 0x33 in which case the new two character code is used
+    pub fn licensee(&self) -> String {
+        if self.old_licensee_code == 0x33 {
+            self.new_licensee_code.clone()
+        } else {
+            format!("{:02x}", self.old_licensee_code)
+        }
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ok = |valid: bool| if valid { "ok" } else { "BAD" };
+
+        writeln!(f, "title:             {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "manufacturer code: {}", code)?;
+        }
+        writeln!(f, "licensee:          {}", self.licensee())?;
+        writeln!(f, "cgb:               {:?}", self.cgb_support)?;
+        writeln!(f, "sgb:               {}", self.sgb_support)?;
+        writeln!(
+            f,
+            "cartridge type:    0x{:02x} ({})",
+            self.cartridge_type,
+            self.cartridge_type_name()
+        )?;
+        match self.rom_size() {
+            Some(size) => writeln!(f, "rom size:          {} KiB", size / 1024)?,
+            None => writeln!(f, "rom size:          unknown (0x{:02x})", self.rom_size_code)?,
+        }
+        writeln!(f, "ram size:          {} KiB", self.ram_size() / 1024)?;
+        writeln!(f, "destination:       {:?}", self.destination)?;
+        writeln!(f, "version:           {}", self.version)?;
+        writeln!(f, "logo:              {}", ok(self.logo_valid))?;
+        writeln!(
+            f,
+            "header checksum:   0x{:02x} {}",
+            self.header_checksum,
+            ok(self.header_checksum_valid)
+        )?;
+        write!(
+            f,
+            "global checksum:   0x{:04x} {}",
+            self.global_checksum,
+            ok(self.global_checksum_valid)
+        )
+    }
+}
+
+// header strings are padded with zeroes, and may contain junk past the end
+pub(crate) fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
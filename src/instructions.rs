@@ -1,5 +1,6 @@
 use std::convert::From;
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum Instruction {
     ADD(ArithTarget),
     ADC(ArithTarget),
@@ -58,6 +59,7 @@ pub enum Instruction {
     EI,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub enum ArithTarget {
     A,
@@ -78,6 +80,8 @@ pub enum AddHLTarget {
     SP,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum IncDecTarget {
     A,
     B,
@@ -93,6 +97,7 @@ pub enum IncDecTarget {
     SP,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub enum PrefixTarget {
     A,
@@ -141,6 +146,7 @@ pub enum JumpTest {
     Unconditional,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum LoadByteTarget {
     A,
     B,
@@ -152,6 +158,7 @@ pub enum LoadByteTarget {
     HLI,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub enum LoadByteSource {
     A,
@@ -172,6 +179,7 @@ pub enum LoadWordTarget {
     SP,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum LoadIndirectTarget {
     // note that CI is more like (0xff00 + C)
     BCI,
@@ -238,7 +246,7 @@ impl Instruction {
             ))),
             0x07 => Some(Instruction::RLCA),
             0x08 => Some(Instruction::LD(LoadType::IndirectFromSP)),
-            0x09 => Some(Instruction::ADDHL(AddHLTarget::HL)),
+            0x09 => Some(Instruction::ADDHL(AddHLTarget::BC)),
             0x0a => Some(Instruction::LD(LoadType::AFromIndirect(
                 LoadIndirectTarget::BCI,
             ))),
@@ -284,8 +292,8 @@ impl Instruction {
                 LoadIndirectTarget::HLIPLUS,
             ))),
             0x23 => Some(Instruction::INC(IncDecTarget::HL)),
-            0x24 => Some(Instruction::INC(IncDecTarget::D)),
-            0x25 => Some(Instruction::DEC(IncDecTarget::D)),
+            0x24 => Some(Instruction::INC(IncDecTarget::H)),
+            0x25 => Some(Instruction::DEC(IncDecTarget::H)),
             0x26 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::H,
                 LoadByteSource::D8,
@@ -296,7 +304,7 @@ impl Instruction {
             0x2a => Some(Instruction::LD(LoadType::AFromIndirect(
                 LoadIndirectTarget::HLIPLUS,
             ))),
-            0x2b => Some(Instruction::DEC(IncDecTarget::DE)),
+            0x2b => Some(Instruction::DEC(IncDecTarget::HL)),
             0x2c => Some(Instruction::INC(IncDecTarget::L)),
             0x2d => Some(Instruction::DEC(IncDecTarget::L)),
             0x2e => Some(Instruction::LD(LoadType::Byte(
@@ -334,7 +342,7 @@ impl Instruction {
 
             0x40 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::B,
-                LoadByteSource::C,
+                LoadByteSource::B,
             ))),
             0x41 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::B,
@@ -399,7 +407,7 @@ impl Instruction {
 
             0x50 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::D,
-                LoadByteSource::C,
+                LoadByteSource::B,
             ))),
             0x51 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::D,
@@ -464,7 +472,7 @@ impl Instruction {
 
             0x60 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::H,
-                LoadByteSource::C,
+                LoadByteSource::B,
             ))),
             0x61 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::H,
@@ -529,7 +537,7 @@ impl Instruction {
 
             0x70 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::HLI,
-                LoadByteSource::C,
+                LoadByteSource::B,
             ))),
             0x71 => Some(Instruction::LD(LoadType::Byte(
                 LoadByteTarget::HLI,
@@ -597,14 +605,14 @@ impl Instruction {
             0x85 => Some(Instruction::ADD(ArithTarget::L)),
             0x86 => Some(Instruction::ADD(ArithTarget::HLI)),
             0x87 => Some(Instruction::ADD(ArithTarget::A)),
-            0x88 => Some(Instruction::ADD(ArithTarget::B)),
-            0x89 => Some(Instruction::ADD(ArithTarget::C)),
-            0x8a => Some(Instruction::ADD(ArithTarget::D)),
-            0x8b => Some(Instruction::ADD(ArithTarget::E)),
-            0x8c => Some(Instruction::ADD(ArithTarget::H)),
-            0x8d => Some(Instruction::ADD(ArithTarget::L)),
-            0x8e => Some(Instruction::ADD(ArithTarget::HLI)),
-            0x8f => Some(Instruction::ADD(ArithTarget::A)),
+            0x88 => Some(Instruction::ADC(ArithTarget::B)),
+            0x89 => Some(Instruction::ADC(ArithTarget::C)),
+            0x8a => Some(Instruction::ADC(ArithTarget::D)),
+            0x8b => Some(Instruction::ADC(ArithTarget::E)),
+            0x8c => Some(Instruction::ADC(ArithTarget::H)),
+            0x8d => Some(Instruction::ADC(ArithTarget::L)),
+            0x8e => Some(Instruction::ADC(ArithTarget::HLI)),
+            0x8f => Some(Instruction::ADC(ArithTarget::A)),
 
             0x90 => Some(Instruction::SUB(ArithTarget::B)),
             0x91 => Some(Instruction::SUB(ArithTarget::C)),
@@ -613,7 +621,7 @@ impl Instruction {
             0x94 => Some(Instruction::SUB(ArithTarget::H)),
             0x95 => Some(Instruction::SUB(ArithTarget::L)),
             0x96 => Some(Instruction::SUB(ArithTarget::HLI)),
-            0x97 => Some(Instruction::SBC(ArithTarget::A)),
+            0x97 => Some(Instruction::SUB(ArithTarget::A)),
             0x98 => Some(Instruction::SBC(ArithTarget::B)),
             0x99 => Some(Instruction::SBC(ArithTarget::C)),
             0x9a => Some(Instruction::SBC(ArithTarget::D)),
@@ -0,0 +1,32 @@
+// the five interrupt sources, in priority order
+//
+// each one owns the bit in IE/IF matching its position here,
+// and jumps to 0x40 + 8 * position when it's serviced
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+pub const INTERRUPTS: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    // the bit this interrupt uses in IE and IF
+    pub fn mask(self) -> u8 {
+        0x1 << self as u8
+    }
+
+    // the address the cpu jumps to when servicing this interrupt
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+}
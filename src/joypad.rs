@@ -0,0 +1,101 @@
+use crate::interrupts::Interrupt;
+
+// the eight buttons on the dmg
+//
+// the first four are read through the direction select line, the last four
+// through the action select line, each in the order of the bits in P1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+// P1 (0xff00)
+//
+// the game picks which group of buttons to read by pulling bit 4 (directions)
+// or bit 5 (actions) low, then reads the lower 4 bits where 0 means pressed
+pub struct Joypad {
+    // one bit per button, set while the button is held down
+    pressed: u8,
+    // bits 4 and 5 of P1, as last written by the game
+    select: u8,
+    interrupt: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            pressed: 0,
+            select: 0x30,
+            interrupt: false,
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let mask = 0x1 << button as u8;
+        let old_lines = self.lines();
+
+        if pressed {
+            self.pressed |= mask;
+        } else {
+            self.pressed &= !mask;
+        }
+
+        self.check_interrupt(old_lines);
+    }
+
+    pub fn read(&self) -> u8 {
+        // the top two bits don't exist and read as 1
+        0xc0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, new_byte: u8) {
+        let old_lines = self.lines();
+
+        // only the select lines can be written
+        self.select = new_byte & 0x30;
+
+        self.check_interrupt(old_lines);
+    }
+
+    // returns the joypad interrupt if one was raised since the last call, in the same layout as IF
+    pub fn take_interrupt(&mut self) -> u8 {
+        let interrupt = self.interrupt;
+        self.interrupt = false;
+
+        if interrupt {
+            Interrupt::Joypad.mask()
+        } else {
+            0
+        }
+    }
+
+    // the lower 4 bits of P1, where each line is pulled low by a pressed button
+    // in any group that is currently selected
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+
+        if self.select & 0x10 == 0 {
+            pressed |= self.pressed & 0xf;
+        }
+
+        if self.select & 0x20 == 0 {
+            pressed |= self.pressed >> 4;
+        }
+
+        !pressed & 0xf
+    }
+
+    // the interrupt fires when any of the lines goes from high to low
+    fn check_interrupt(&mut self, old_lines: u8) {
+        if old_lines & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+}
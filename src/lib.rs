@@ -0,0 +1,38 @@
+mod apu;
+mod cartridge;
+mod cpu;
+mod flags;
+pub mod frontend;
+pub mod gbs;
+mod gpu;
+pub mod header;
+mod instructions;
+mod interrupts;
+mod joypad;
+pub mod link;
+mod linked_pair;
+mod memory_bus;
+mod memory_map;
+mod png;
+pub mod printer;
+mod registers;
+mod rtc;
+mod save_file;
+mod serial;
+pub mod terminal;
+mod timer;
+pub mod wav;
+#[cfg(feature = "window")]
+pub mod window;
+
+mod emulator;
+
+pub use apu::{Channel, CHANNELS, CLOCK_SPEED};
+pub use cpu::{Model, CYCLES_PER_FRAME};
+pub use emulator::Emulator;
+pub use gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use joypad::Button;
+pub use linked_pair::LinkedPair;
+pub use rtc::Clock;
+pub use save_file::SaveFile;
+pub use serial::{SerialCapture, SerialDevice};
@@ -0,0 +1,361 @@
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::serial::SerialDevice;
+
+// bumped whenever the messages change, so mismatched builds refuse to link
+const PROTOCOL_VERSION: u8 = 1;
+
+// each side may run at most this many cycles ahead of the other,
+// which keeps transfers landing at about the same point in both games
+const SYNC_INTERVAL: u64 = 4096;
+
+// every message is a kind, a byte and the sender's cycle count
+const MESSAGE_SIZE: usize = 10;
+
+const HELLO: u8 = 0;
+// the sender has reached the given cycle
+const SYNC: u8 = 1;
+// the sender clocked out a byte with its internal clock and wants one back
+const TRANSFER: u8 = 2;
+// the answer to a transfer
+const REPLY: u8 = 3;
+
+// the byte read when nothing is clocking data in on the other end
+const DISCONNECTED: u8 = 0xff;
+
+struct Message {
+    kind: u8,
+    byte: u8,
+    cycles: u64,
+}
+
+// a connection to the other console, over tcp or a unix socket
+trait Stream: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// a link cable to another emulator process on the same machine
+//
+// addresses are either "host:port" for tcp, or "unix:/path/to/socket"
+//
+// whichever side listens is the host, and stays the clock master if both games start
+// a transfer with the internal clock at once, the guest then answers the host's transfer
+// instead of its own. the two sides also trade their cycle counts as they run, and wait
+// for each other so neither gets more than SYNC_INTERVAL cycles ahead
+pub struct LinkCable {
+    // none once the other side has gone away, after which the port acts unplugged
+    stream: Option<Box<dyn Stream>>,
+    host: bool,
+    // bytes read that don't make up a whole message yet
+    incoming: Vec<u8>,
+    cycles: u64,
+    peer_cycles: u64,
+    // the byte the console has ready, while it has started a transfer the other side hasn't clocked
+    ready: Option<u8>,
+    // a byte clocked in by the other side, to hand to the console
+    received: Option<u8>,
+}
+
+impl LinkCable {
+    // wait for the other side to connect, and become the host
+    pub fn listen(address: &str) -> io::Result<LinkCable> {
+        let stream: Box<dyn Stream> = match unix_path(address) {
+            #[cfg(unix)]
+            Some(path) => Box::new(accept_unix(path)?),
+            #[cfg(not(unix))]
+            Some(_) => return Err(io::Error::other("no unix sockets here")),
+            None => {
+                let (stream, _) = TcpListener::bind(address)?.accept()?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+        };
+
+        LinkCable::handshake(stream, true)
+    }
+
+    // connect to a host that is already listening
+    pub fn connect(address: &str) -> io::Result<LinkCable> {
+        let stream: Box<dyn Stream> = match unix_path(address) {
+            #[cfg(unix)]
+            Some(path) => Box::new(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Some(_) => return Err(io::Error::other("no unix sockets here")),
+            None => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+        };
+
+        LinkCable::handshake(stream, false)
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // both sides say hello and check they speak the same protocol,
+    // the host's hello also tells the guest which role it has been given
+    fn handshake(stream: Box<dyn Stream>, host: bool) -> io::Result<LinkCable> {
+        let mut link = LinkCable {
+            stream: Some(stream),
+            host,
+            incoming: Vec::new(),
+            cycles: 0,
+            peer_cycles: 0,
+            ready: None,
+            received: None,
+        };
+
+        link.send(HELLO, PROTOCOL_VERSION);
+
+        let hello = match link.receive(true) {
+            Some(message) if message.kind == HELLO => message,
+            _ => return Err(invalid_data("no hello from the other side")),
+        };
+
+        if hello.byte != PROTOCOL_VERSION {
+            return Err(invalid_data("the other side uses a different protocol version"));
+        }
+
+        // the cycle count of a hello says whether the sender is the host
+        if (hello.cycles != 0) == host {
+            return Err(invalid_data("both sides want the same role"));
+        }
+
+        Ok(link)
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) {
+        let cycles = if kind == HELLO { self.host as u64 } else { self.cycles };
+
+        let mut message = [0; MESSAGE_SIZE];
+        message[0] = kind;
+        message[1] = byte;
+        message[2..].copy_from_slice(&cycles.to_le_bytes());
+
+        let sent = match &mut self.stream {
+            Some(stream) => stream.set_nonblocking(false).and_then(|_| stream.write_all(&message)),
+            None => return,
+        };
+
+        if sent.is_err() {
+            self.stream = None;
+        }
+    }
+
+    // the next whole message from the other side, if there is one
+    //
+    // when blocking this waits for one, only giving up if the connection is lost
+    fn receive(&mut self, block: bool) -> Option<Message> {
+        let mut buffer = [0; 256];
+
+        while self.incoming.len() < MESSAGE_SIZE {
+            let stream = self.stream.as_mut()?;
+
+            let read = stream.set_nonblocking(!block).and_then(|_| stream.read(&mut buffer));
+            match read {
+                Ok(0) => self.stream = None,
+                Ok(length) => self.incoming.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return None,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => self.stream = None,
+            }
+        }
+
+        let message: Vec<u8> = self.incoming.drain(..MESSAGE_SIZE).collect();
+        let mut cycles = [0; 8];
+        cycles.copy_from_slice(&message[2..]);
+
+        Some(Message {
+            kind: message[0],
+            byte: message[1],
+            cycles: u64::from_le_bytes(cycles),
+        })
+    }
+
+    // tell the other side how far we've got, and wait for it if it's fallen behind
+    fn sync(&mut self) {
+        self.send(SYNC, 0);
+
+        // answer anything that arrived, then hold back until the other side catches up
+        while let Some(message) = self.receive(false) {
+            self.handle(message);
+        }
+
+        while self.peer_cycles + SYNC_INTERVAL < self.cycles {
+            match self.receive(true) {
+                Some(message) => self.handle(message),
+                None => break,
+            }
+        }
+    }
+
+    // deal with a message that isn't the reply to one of our transfers
+    fn handle(&mut self, message: Message) {
+        self.peer_cycles = self.peer_cycles.max(message.cycles);
+
+        if message.kind == TRANSFER {
+            // the other side is clocking us, if the game isn't ready the bits are lost.
+            // a transfer we've started with our own clock is done with this one instead,
+            // which is how the guest answers the host's when both start one at once
+            match self.ready.take() {
+                Some(byte) => {
+                    self.send(REPLY, byte);
+                    self.received = Some(message.byte);
+                }
+                None => self.send(REPLY, DISCONNECTED),
+            }
+        }
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        // the other side's transfer got here first and has been answered already
+        if let Some(received) = self.received.take() {
+            return received;
+        }
+
+        self.send(TRANSFER, byte);
+
+        // wait for the answer, while still keeping up with everything else the other side sends
+        loop {
+            let message = match self.receive(true) {
+                Some(message) => message,
+                None => return DISCONNECTED,
+            };
+
+            self.peer_cycles = self.peer_cycles.max(message.cycles);
+
+            match message.kind {
+                REPLY => return message.byte,
+                // both sides started a transfer, the host's wins and the guest answers it,
+                // so the host ignores the guest's and keeps waiting
+                TRANSFER if !self.host => {
+                    self.send(REPLY, byte);
+                    return message.byte;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn external_transfer(&mut self) -> Option<u8> {
+        while let Some(message) = self.receive(false) {
+            self.handle(message);
+        }
+
+        self.received.take()
+    }
+
+    fn step(&mut self, cycles: u8, ready: Option<u8>) {
+        // once answered, the byte stays in SB until the console picks up the reply
+        self.ready = if self.received.is_some() { None } else { ready };
+
+        let before = self.cycles / SYNC_INTERVAL;
+        self.cycles += cycles as u64;
+
+        if self.stream.is_some() && self.cycles / SYNC_INTERVAL != before {
+            self.sync();
+        }
+    }
+}
+
+// wait for the other side on a unix socket at the given path
+//
+// the socket file is removed once connected, since it isn't needed after that, but one
+// can still be left behind by a listener that was killed while waiting. that's only
+// replaced when nothing answers on it, so a listener that's still running keeps its socket
+#[cfg(unix)]
+fn accept_unix(path: &str) -> io::Result<UnixStream> {
+    let listener = match UnixListener::bind(path) {
+        Err(error) if error.kind() == ErrorKind::AddrInUse => match UnixStream::connect(path) {
+            Err(stale) if stale.kind() == ErrorKind::ConnectionRefused => {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            _ => return Err(error),
+        },
+        result => result?,
+    };
+    let accepted = listener.accept();
+    let _ = fs::remove_file(path);
+
+    Ok(accepted?.0)
+}
+
+fn unix_path(address: &str) -> Option<&str> {
+    address.strip_prefix("unix:")
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    // run a transfer with the internal clock from SB, the way the serial port drives a device
+    fn clock_transfer(link: &mut LinkCable, byte: u8) -> u8 {
+        for _ in 0..SYNC_INTERVAL / 4 {
+            link.step(4, Some(byte));
+        }
+
+        link.transfer(byte)
+    }
+
+    #[test]
+    fn bytes_swap_when_both_sides_clock_at_once() {
+        let path = env::temp_dir().join(format!("gameboy-link-test-{}", process::id()));
+        let address = format!("unix:{}", path.display());
+
+        let host = {
+            let address = address.clone();
+            thread::spawn(move || clock_transfer(&mut LinkCable::listen(&address).unwrap(), 0x42))
+        };
+
+        let mut guest = loop {
+            match LinkCable::connect(&address) {
+                Ok(link) => break link,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        // hanging up lets the host finish even if its transfer was never answered
+        let received = clock_transfer(&mut guest, 0x99);
+        drop(guest);
+
+        // each side gets the other's byte, rather than both reading an unplugged port
+        assert_eq!(received, 0x42);
+        assert_eq!(host.join().unwrap(), 0x99);
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::CYCLES_PER_FRAME;
+use crate::emulator::Emulator;
+use crate::serial::SerialDevice;
+
+// the byte read when the other console isn't ready to be clocked
+const NOT_READY: u8 = 0xff;
+
+// the state shared by both ends of a virtual cable
+struct Wire {
+    // the byte each console has ready, while it has started a transfer the other hasn't clocked
+    ready: [Option<u8>; 2],
+    // a byte clocked into each console by the other, to hand over when it next asks
+    received: [Option<u8>; 2],
+}
+
+// one end of a link cable that never leaves the process
+struct CableEnd {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl SerialDevice for CableEnd {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+        wire.ready[self.side] = None;
+
+        // the other console's clock got there first
+        if let Some(received) = wire.received[self.side].take() {
+            return received;
+        }
+
+        match wire.ready[other].take() {
+            Some(reply) => {
+                wire.received[other] = Some(byte);
+                reply
+            }
+            None => NOT_READY,
+        }
+    }
+
+    fn external_transfer(&mut self) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+
+        let received = wire.received[self.side].take();
+        if received.is_some() {
+            wire.ready[self.side] = None;
+        }
+
+        received
+    }
+
+    fn step(&mut self, _cycles: u8, ready: Option<u8>) {
+        let mut wire = self.wire.borrow_mut();
+        wire.ready[self.side] = if wire.received[self.side].is_some() { None } else { ready };
+    }
+}
+
+// two consoles in one process, joined by a virtual link cable
+//
+// they're stepped in lockstep, always running whichever is behind, so transfers
+// between them happen at the same point every run
+pub struct LinkedPair {
+    consoles: [Emulator; 2],
+    cycles: [u64; 2],
+}
+
+impl LinkedPair {
+    pub fn new(first: Emulator, second: Emulator) -> LinkedPair {
+        let wire = Rc::new(RefCell::new(Wire {
+            ready: [None; 2],
+            received: [None; 2],
+        }));
+
+        let mut consoles = [first, second];
+        for (side, console) in consoles.iter_mut().enumerate() {
+            console.set_serial_device(Box::new(CableEnd {
+                wire: wire.clone(),
+                side,
+            }));
+        }
+
+        LinkedPair {
+            consoles,
+            cycles: [0; 2],
+        }
+    }
+
+    pub fn first(&self) -> &Emulator {
+        &self.consoles[0]
+    }
+
+    pub fn first_mut(&mut self) -> &mut Emulator {
+        &mut self.consoles[0]
+    }
+
+    pub fn second(&self) -> &Emulator {
+        &self.consoles[1]
+    }
+
+    pub fn second_mut(&mut self) -> &mut Emulator {
+        &mut self.consoles[1]
+    }
+
+    // execute a single instruction on whichever console is behind,
+    // with ties going to the first
+    pub fn step(&mut self) {
+        let side = if self.cycles[1] < self.cycles[0] { 1 } else { 0 };
+        self.cycles[side] += self.consoles[side].step() as u64;
+    }
+
+    // run until both consoles have gone the given number of cycles past the one furthest on
+    pub fn run_cycles(&mut self, cycles: u64) {
+        let target = self.cycles[0].max(self.cycles[1]) + cycles;
+
+        while self.cycles[0] < target || self.cycles[1] < target {
+            self.step();
+        }
+    }
+
+    // run both consoles for a frame's worth of cycles
+    pub fn run_frame(&mut self) {
+        self.run_cycles(CYCLES_PER_FRAME as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_emulator;
+    use crate::interrupts::Interrupt;
+    use crate::memory_map::{IF_REGISTER, SB_REGISTER, SC_REGISTER};
+
+    // a rom that puts a byte in SB, starts a transfer with the given SC, then spins
+    fn transfer_rom(byte: u8, control: u8) -> Emulator {
+        let code = [
+            0xf3, // di
+            0x3e, byte, 0xe0, 0x01, // ld a, byte; ldh (SB), a
+            0x3e, control, 0xe0, 0x02, // ld a, control; ldh (SC), a
+            0x18, 0xfe, // jr -2
+        ];
+
+        test_emulator(0x00, &code)
+    }
+
+    #[test]
+    fn bytes_swap_between_the_consoles() {
+        // the first clocks the transfer, the second waits on the first's clock
+        let mut pair = LinkedPair::new(transfer_rom(0x42, 0x81), transfer_rom(0x99, 0x80));
+        pair.run_frame();
+
+        for (console, byte) in [(pair.first(), 0x99), (pair.second(), 0x42)] {
+            let bus = console.cpu().bus();
+            assert_eq!(bus.read_byte(SB_REGISTER), byte);
+            // the transfer is over, so the start bit has been cleared
+            assert_eq!(bus.read_byte(SC_REGISTER) & 0x80, 0x00);
+            assert_ne!(bus.read_byte(IF_REGISTER) & Interrupt::Serial.mask(), 0x00);
+        }
+    }
+
+    #[test]
+    fn bytes_swap_when_both_consoles_clock() {
+        let mut pair = LinkedPair::new(transfer_rom(0x42, 0x81), transfer_rom(0x99, 0x81));
+        pair.run_frame();
+
+        for (console, byte) in [(pair.first(), 0x99), (pair.second(), 0x42)] {
+            let bus = console.cpu().bus();
+            assert_eq!(bus.read_byte(SB_REGISTER), byte);
+            assert_ne!(bus.read_byte(IF_REGISTER) & Interrupt::Serial.mask(), 0x00);
+        }
+    }
+
+    #[test]
+    fn nothing_happens_without_a_clock() {
+        // both waiting on the other's clock means neither transfer ever finishes
+        let mut pair = LinkedPair::new(transfer_rom(0x42, 0x80), transfer_rom(0x99, 0x80));
+        pair.run_frame();
+
+        for (console, byte) in [(pair.first(), 0x42), (pair.second(), 0x99)] {
+            let bus = console.cpu().bus();
+            assert_eq!(bus.read_byte(SB_REGISTER), byte);
+            assert_eq!(bus.read_byte(SC_REGISTER) & 0x80, 0x80);
+            assert_eq!(bus.read_byte(IF_REGISTER) & Interrupt::Serial.mask(), 0x00);
+        }
+    }
+}
@@ -1,46 +1,327 @@
 use std::io;
 use std::io::prelude::*;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process;
 
-mod cpu;
-mod flags;
-mod instructions;
-mod memory_bus;
-mod registers;
-mod memory_map;
-mod gpu;
+use gameboy_emulator::gbs::Gbs;
+use gameboy_emulator::header::CartridgeHeader;
+use gameboy_emulator::link::LinkCable;
+use gameboy_emulator::printer::Printer;
+use gameboy_emulator::terminal::Terminal;
+use gameboy_emulator::wav::WavWriter;
+#[cfg(feature = "window")]
+use gameboy_emulator::window::{Control, KeyMap, Window};
+use gameboy_emulator::{Emulator, Model, SaveFile, SerialCapture, CHANNELS, CLOCK_SPEED, CYCLES_PER_FRAME};
 
-use cpu::CPU;
+// the rate audio is recorded at
+const SAMPLE_RATE: u32 = 44100;
+
+// how long gbs-play plays for, since songs usually loop forever
+const GBS_SECONDS: u64 = 120;
+
+// how many times bigger than the game boy's screen the window is, unless --scale is given
+#[cfg(feature = "window")]
+const WINDOW_SCALE: usize = 4;
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    if args.len() > 1 && args[1] == "rom-info" {
+        rom_info(&args[2..]);
+        return Ok(());
+    }
+
+    if args.len() > 1 && args[1] == "gbs-play" {
+        return gbs_play(args.split_off(2));
+    }
+
+    // --listen <address> or --connect <address> links up with another instance,
+    // --printer <directory> plugs in a printer that saves what it prints there
+    let listen = take_option(&mut args, "--listen");
+    let connect = take_option(&mut args, "--connect");
+    let printer = take_option(&mut args, "--printer");
+    // --record <file.wav> saves the audio, --frames <n> stops after that many frames
+    let record = take_option(&mut args, "--record");
+    // --stems <directory> saves each sound channel to its own file there
+    let stems = take_option(&mut args, "--stems");
+    let frames = take_option(&mut args, "--frames")
+        .map(|frames| frames.parse::<u64>().expect("Number of frames must be a number!"));
+    // --terminal shows the game in the terminal, and plays at the real speed
+    let use_terminal = take_flag(&mut args, "--terminal");
+    // --window shows the game in a desktop window instead, see open_window for its options
+    #[cfg(feature = "window")]
+    let mut window = open_window(&mut args)?;
+    #[cfg(not(feature = "window"))]
+    if args.iter().any(|arg| arg == "--window") {
+        panic!("Must be built with --features window to use --window!");
+    }
+
+    let game_path = Path::new(&args[args.len() - 1]);
+
+    let mut emulator = match load_emulator(&args) {
+        Ok(emulator) => emulator,
+        Err(error) => {
+            eprintln!("Could not load {}: {}", game_path.display(), error);
+            process::exit(1);
+        }
+    };
+
+    if let Some(address) = listen {
+        println!("waiting for the other side to connect on {}", address);
+        emulator.set_serial_device(Box::new(LinkCable::listen(&address)?));
+    } else if let Some(address) = connect {
+        emulator.set_serial_device(Box::new(LinkCable::connect(&address)?));
+    } else if let Some(directory) = printer {
+        emulator.set_serial_device(Box::new(Printer::new(directory)));
+    } else if !use_terminal {
+        // test roms report their results over serial, so show whatever is sent
+        emulator.set_serial_device(Box::new(SerialCapture::stdout()));
+    }
+
+    let mut save_file = SaveFile::for_rom(game_path);
+    save_file.load(&mut emulator)?;
+
+    // the sound card decides the rate when there's a window playing the sound
+    #[cfg(feature = "window")]
+    let window_sample_rate = window.as_ref().and_then(|window| window.sample_rate());
+    #[cfg(not(feature = "window"))]
+    let window_sample_rate = None;
+
+    let sample_rate = match window_sample_rate {
+        Some(rate) => Some(rate),
+        None if record.is_some() || stems.is_some() => Some(SAMPLE_RATE),
+        None => None,
+    };
+    start_audio(&mut emulator, sample_rate, stems.is_some());
+
+    let mut recorder = match record {
+        Some(path) => Some(WavWriter::create(path, sample_rate.unwrap(), 2)?),
+        None => None,
+    };
+
+    let mut stem_recorders = Vec::new();
+    if let Some(directory) = stems {
+        fs::create_dir_all(&directory)?;
+
+        for channel in CHANNELS.iter() {
+            let path = Path::new(&directory).join(format!("{}.wav", channel.name()));
+            stem_recorders.push(WavWriter::create(path, sample_rate.unwrap(), 1)?);
+        }
+    }
+
+    let mut terminal = if use_terminal { Some(Terminal::new()?) } else { None };
+
+    let mut frame = 0;
+    while frames.is_none_or(|frames| frame < frames) {
+        if let Some(terminal) = &mut terminal {
+            if !terminal.update_input(&mut emulator) {
+                break;
+            }
+        }
+
+        #[cfg(feature = "window")]
+        if let Some(window) = &mut window {
+            match window.update_input(&mut emulator) {
+                Control::Quit => break,
+                Control::Paused => {
+                    window.draw(emulator.framebuffer())?;
+                    window.wait_for_next_frame();
+                    continue;
+                }
+                Control::Reset => {
+                    // the fresh game gets the same save, link port and sound as the old one
+                    save_file.flush(&mut emulator)?;
+                    let device = emulator.take_serial_device();
+
+                    emulator = load_emulator(&args)?;
+                    emulator.set_serial_device(device);
+                    start_audio(&mut emulator, sample_rate, !stem_recorders.is_empty());
+                    save_file.load(&mut emulator)?;
+                }
+                Control::Run => {}
+            }
+        }
+
+        emulator.run_frame();
+        frame += 1;
+
+        if let Some(terminal) = &mut terminal {
+            terminal.draw(emulator.framebuffer())?;
+            terminal.wait_for_next_frame();
+        }
+
+        // always take the mixed samples, so they don't pile up when only recording stems
+        let samples = emulator.take_samples();
+        if let Some(recorder) = &mut recorder {
+            recorder.write_samples(&samples)?;
+        }
+
+        if !stem_recorders.is_empty() {
+            for (recorder, stem) in stem_recorders.iter_mut().zip(emulator.take_stems().iter()) {
+                recorder.write_samples(stem)?;
+            }
+        }
+
+        #[cfg(feature = "window")]
+        if let Some(window) = &mut window {
+            window.play_samples(&samples);
+            window.draw(emulator.framebuffer())?;
+            window.wait_for_next_frame();
+        }
+
+        save_file.frame(&mut emulator)?;
+    }
+
+    for recorder in recorder.into_iter().chain(stem_recorders) {
+        recorder.finish()?;
+    }
 
-    if args.len() != 3 {
-        panic!("Must give bios and game rom!")
+    save_file.flush(&mut emulator)
+}
+
+// build the emulator from the command line, which ends with an optional bios then the game
+fn load_emulator(args: &[String]) -> io::Result<Emulator> {
+    let emulator = match args.len() {
+        // with only a game, skip the boot rom entirely
+        2 => Emulator::without_bios(read_file(&args[1])?, Model::Dmg)?,
+        // first arg is the path to bios
+        // second arg is the path to game rom
+        3 => {
+            Emulator::new(read_file(&args[1])?, read_file(&args[2])?)?
+        }
+        _ => panic!("Must give a game rom, optionally after a bios!"),
+    };
+
+    Ok(emulator)
+}
+
+// have the emulator produce audio if anything is going to listen to it
+fn start_audio(emulator: &mut Emulator, sample_rate: Option<u32>, stems: bool) {
+    if let Some(sample_rate) = sample_rate {
+        emulator.set_sample_rate(sample_rate);
+    }
+    emulator.set_stems_enabled(stems);
+}
+
+// open a window if --window was given, taking its options from the arguments
+//
+// --scale <n> makes each pixel n by n, and --keys <bindings> changes the keys, e.g. "a=k,b=j"
+#[cfg(feature = "window")]
+fn open_window(args: &mut Vec<String>) -> io::Result<Option<Window>> {
+    let use_window = take_flag(args, "--window");
+    let scale = take_option(args, "--scale")
+        .map_or(WINDOW_SCALE, |scale| scale.parse().expect("Scale must be a number!"));
+    let key_map = match take_option(args, "--keys") {
+        Some(keys) => match KeyMap::parse(&keys) {
+            Some(key_map) => key_map,
+            None => panic!("Keys must be given as action=key pairs separated by commas!"),
+        },
+        None => KeyMap::new(),
+    };
+
+    if use_window {
+        Window::new(scale, key_map).map(Some)
+    } else {
+        Ok(None)
     }
+}
 
-    // open files from args
-    // first arg is the path to bios
-    // second arg is the path to game rom
-    let mut bios = File::open(String::from(&args[1]))?;
-    let mut game = File::open(String::from(&args[2]))?;
+// render a song from a gbs rip to a wav file
+//
+// gbs-play <file.gbs> <out.wav> [--song <n>] [--seconds <n>]
+fn gbs_play(mut args: Vec<String>) -> io::Result<()> {
+    let song = take_option(&mut args, "--song");
+    let seconds = take_option(&mut args, "--seconds")
+        .map_or(GBS_SECONDS, |seconds| seconds.parse().expect("Number of seconds must be a number!"));
 
-    // load bytes of file into a buffer
-    let mut bios_buffer = Vec::new();
-    bios.read_to_end(&mut bios_buffer)?;
+    if args.len() != 2 {
+        panic!("Must give a gbs file and a wav file to write!");
+    }
 
-    let mut game_buffer = Vec::new();
-    game.read_to_end(&mut game_buffer)?;
+    let gbs = match Gbs::parse(&read_file(&args[0])?) {
+        Some(gbs) => gbs,
+        None => panic!("Not a playable gbs file!"),
+    };
+    println!("{}", gbs.header);
 
-    if bios_buffer.len() != 256 {
-        panic!("BIOS is the wrong size!");
+    // songs are numbered from 1 on the command line, like the header's first song
+    let song = song.map_or(gbs.header.first_song, |song| song.parse().expect("Song must be a number!"));
+    if song == 0 || song > gbs.header.song_count {
+        panic!("Song must be between 1 and {}!", gbs.header.song_count);
     }
 
+    let mut emulator = Emulator::from_gbs(&gbs, song - 1)?;
+    emulator.set_sample_rate(SAMPLE_RATE);
+
+    let mut recorder = WavWriter::create(&args[1], SAMPLE_RATE, 2)?;
 
-    let mut cpu = CPU::new(bios_buffer, game_buffer);
+    // run whole frames until enough has been played, going purely by emulated time
+    let frames = seconds * CLOCK_SPEED as u64 / CYCLES_PER_FRAME as u64;
+    for _ in 0..frames {
+        emulator.run_frame();
+        recorder.write_samples(&emulator.take_samples())?;
+    }
 
-    println!("Hello, world!");
+    recorder.finish()?;
 
     Ok(())
 }
+
+// remove a flag and the value after it from the arguments
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+
+    if i + 1 >= args.len() {
+        panic!("Must give a value after {}!", name);
+    }
+
+    let value = args.remove(i + 1);
+    args.remove(i);
+
+    Some(value)
+}
+
+// remove a flag without a value from the arguments, returning whether it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+// load bytes of file into a buffer
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+// print the header of every rom given, so dumps can be checked in bulk
+fn rom_info(paths: &[String]) {
+    if paths.is_empty() {
+        panic!("Must give at least one rom!")
+    }
+
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+
+        println!("{}", path);
+
+        match std::fs::read(path) {
+            Ok(rom) => match CartridgeHeader::parse(&rom) {
+                Some(header) => println!("{}", header),
+                None => println!("too short to contain a header ({} bytes)", rom.len()),
+            },
+            Err(error) => println!("could not read: {}", error),
+        }
+    }
+}
@@ -1,99 +1,315 @@
+use std::io;
+
+use crate::apu::Apu;
+use crate::cartridge::Cartridge;
 use crate::gpu::GPU;
+use crate::interrupts::{Interrupt, INTERRUPTS};
+use crate::joypad::Joypad;
 use crate::memory_map::*;
+use crate::serial::Serial;
+use crate::timer::Timer;
+
+const BIOS_SIZE: usize = 0x100;
+
+// oam dma copies one byte per m-cycle
+const DMA_LENGTH: u16 = 0xa0;
 
 // abstract memory into its logical parts instead of one big array
 // currently do not have an implementation for echo ram
 pub struct MemoryBus {
-    bios: [u8; 0xff],
-    rom_bank0: [u8; 0x4000],
-    rom_bankn: [u8; 0x4000],
+    bios: [u8; BIOS_SIZE],
+    // the boot rom covers the start of the cartridge until the game writes to 0xff50
+    bios_mapped: bool,
+    // rom and external ram, banked by the cartridge's mbc
+    pub cartridge: Cartridge,
     // vram: [u8; 0x2000],
-    eram: [u8; 0x2000],
     wram: [u8; 0x2000],
     // oam: [u8; 0xa0],
     hram: [u8; 0x7f],
     // memory: [u8; 0xffff],
-    gpu: GPU,
+    pub gpu: GPU,
+    pub apu: Apu,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub timer: Timer,
+    // IE (0xffff), which interrupts the cpu is allowed to service
+    interrupt_enable: u8,
+    // IF (0xff0f), which interrupts have been requested
+    interrupt_flag: u8,
+    // the last value written to 0xff46, the upper byte of the dma source
+    dma: u8,
+    // how many bytes of the running oam dma have been copied, if one is running
+    dma_progress: Option<u16>,
 }
 
 impl MemoryBus {
-    pub fn new(rom: Vec<u8>, game: Vec<u8>) -> MemoryBus {
-        let mut bios = [0; 0xff];
-        for (i, &byte) in rom.iter().enumerate() {
-            bios[i] = byte;
-        }
-
-        // load the game into memory
-        let mut bank0 = [0; 0x4000];
-        let mut bankn = [0; 0x4000];
-        for i in 0..0x4000 {
-            bank0[i] = game[i];
-            bankn[i] = game[i + 0x4000];
-        }
-
-        /*
-        let mut bankn = [0; 0x4000];
-        for i in 0..0x4000 {
-            bankn[i] = game[i + 0x4000];
+    // the boot rom is either exactly 256 bytes, or empty when the boot is skipped,
+    // in which case the cartridge is visible from 0x0000 straight away
+    pub fn new(rom: Vec<u8>, game: Vec<u8>) -> io::Result<MemoryBus> {
+        let mut bios = [0; BIOS_SIZE];
+        match rom.len() {
+            0 => {}
+            BIOS_SIZE => bios.copy_from_slice(&rom),
+            length => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("boot rom is {} bytes instead of {}", length, BIOS_SIZE),
+                ))
+            }
         }
-        */
 
-        MemoryBus {
-            bios: bios,
-            rom_bank0: bank0,
-            rom_bankn: bankn,
+        Ok(MemoryBus {
+            bios,
+            bios_mapped: !rom.is_empty(),
+            cartridge: Cartridge::new(game)?,
             // vram: [0; 0x2000],
-            eram: [0; 0x2000],
             wram: [0; 0x2000],
             // oam: [0; 0xa0],
             hram: [0; 0x7f],
-            gpu: GPU::new()
+            gpu: GPU::new(),
+            apu: Apu::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
+            interrupt_enable: 0,
+            interrupt_flag: 0,
+            dma: 0xff,
+            dma_progress: None,
+        })
+    }
+
+    // set the i/o registers to the values the dmg boot rom leaves them with
+    pub fn skip_boot(&mut self) {
+        // the boot rom takes long enough that the internal counter has moved on
+        self.timer.set_counter(0xabcc);
+        self.apu.skip_boot();
+
+        let registers = [
+            (P1_REGISTER, 0xcf),
+            (SB_REGISTER, 0x00),
+            (SC_REGISTER, 0x7e),
+            (TIMA_REGISTER, 0x00),
+            (TMA_REGISTER, 0x00),
+            (TAC_REGISTER, 0xf8),
+            (IF_REGISTER, 0xe1),
+            (LCDC_REGISTER, 0x91),
+            (STAT_REGISTER, 0x85),
+            (SCY_REGISTER, 0x00),
+            (SCX_REGISTER, 0x00),
+            (LYC_REGISTER, 0x00),
+            (BGP_REGISTER, 0xfc),
+            (OBP0_REGISTER, 0xff),
+            (OBP1_REGISTER, 0xff),
+            (WY_REGISTER, 0x00),
+            (WX_REGISTER, 0x00),
+            (BOOT_REGISTER, 0x01),
+            (IE_REGISTER, 0x00),
+        ];
+
+        for &(address, value) in registers.iter() {
+            self.set_byte(address, value);
+        }
+    }
+
+    // tick every component on the bus by the number of cycles the cpu just took
+    pub fn step(&mut self, cycles: u8) {
+        for _ in 0..cycles / 4 {
+            self.step_dma();
         }
+
+        self.interrupt_flag |= self.gpu.step(cycles);
+        self.apu.step(cycles);
+        self.interrupt_flag |= self.timer.step(cycles);
+        self.interrupt_flag |= self.serial.step(cycles);
+        self.interrupt_flag |= self.joypad.take_interrupt();
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.mask();
     }
 
-    // broken
+    // the highest priority interrupt that is both requested and enabled
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.interrupt_enable & self.interrupt_flag;
+
+        INTERRUPTS
+            .iter()
+            .copied()
+            .find(|interrupt| pending & interrupt.mask() != 0)
+    }
+
+    // while oam dma is running the cpu can only reach hram,
+    // everything else reads as 0xff and ignores writes
     pub fn read_byte(&self, address: u16) -> u8 {
+        if self.dma_progress.is_some() && !(HRAM_START..=HRAM_END).contains(&address) {
+            return 0xff;
+        }
+
+        self.read_mapped(address)
+    }
+
+    pub fn set_byte(&mut self, address: u16, new_byte: u8) {
+        // except that dma itself can still be started again
+        if self.dma_progress.is_some() && !(HRAM_START..=HRAM_END).contains(&address) && address != DMA_REGISTER {
+            return;
+        }
+
+        self.set_mapped(address, new_byte);
+    }
+
+    // copy the next byte of a running oam dma
+    fn step_dma(&mut self) {
+        let progress = match self.dma_progress {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        // sources past wram read from echo ram, which mirrors it
+        let source = if self.dma >= 0xe0 { self.dma - 0x20 } else { self.dma };
+        let byte = self.read_mapped((source as u16) << 8 | progress);
+        self.gpu.set_oam(progress, byte);
+
+        self.dma_progress = if progress + 1 < DMA_LENGTH {
+            Some(progress + 1)
+        } else {
+            None
+        };
+    }
+
+    fn read_mapped(&self, address: u16) -> u8 {
         match address {
-            BIOS_START..=BIOS_END => self.bios[address as usize],
-            ROM_BANK_0_START..=ROM_BANK_0_END => self.rom_bank0[address as usize],
-            ROM_BANK_N_START..=ROM_BANK_N_END => self.rom_bankn[(address - ROM_BANK_N_START) as usize],
-            VRAM_START..=VRAM_END => self.gpu.vram[(address - VRAM_START) as usize],
-            ERAM_START..=ERAM_END => self.eram[(address - ERAM_START) as usize],
+            BIOS_START..=BIOS_END if self.bios_mapped => self.bios[address as usize],
+            ROM_BANK_0_START..=ROM_BANK_N_END => self.cartridge.read_rom(address),
+            VRAM_START..=VRAM_END => self.gpu.read_vram(address - VRAM_START),
+            ERAM_START..=ERAM_END => self.cartridge.read_ram(address - ERAM_START),
             WRAM_START..=WRAM_END => self.wram[(address - WRAM_START) as usize],
             ECHO_START..=ECHO_END => self.wram[(address - ECHO_START) as usize],
-            OAM_START..=OAM_END => self.gpu.vram[(address - OAM_START) as usize],
+            OAM_START..=OAM_END => self.gpu.read_oam(address - OAM_START),
+            // nothing is wired up here, but games still touch it, e.g. when clearing oam
+            UNUSABLE_START..=UNUSABLE_END => 0x00,
             IO_START..=IO_END => self.read_io_register(address),
             HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize],
-            _ => panic!("Cannot access address 0x{}", address)
+            IE_REGISTER => self.interrupt_enable,
         }
     }
 
-    // broken
-    pub fn set_byte(&mut self, address: u16, new_byte: u8) {
+    fn set_mapped(&mut self, address: u16, new_byte: u8) {
         match address {
-            BIOS_START..=BIOS_END => self.bios[address as usize] = new_byte,
-            ROM_BANK_0_START..=ROM_BANK_0_END => self.rom_bank0[address as usize] = new_byte,
-            ROM_BANK_N_START..=ROM_BANK_N_END => self.rom_bankn[(address - ROM_BANK_N_START) as usize] = new_byte,
+            // the rom can't be written to, these go to the mbc instead
+            ROM_BANK_0_START..=ROM_BANK_N_END => self.cartridge.write_rom(address, new_byte),
             // VRAM_START..=VRAM_END => self.gpu.vram[(address - VRAM_START) as usize] = new_byte,
             VRAM_START..=VRAM_END => self.gpu.set_vram(address - VRAM_START, new_byte),
-            ERAM_START..=ERAM_END => self.eram[(address - ERAM_START) as usize] = new_byte,
+            ERAM_START..=ERAM_END => self.cartridge.write_ram(address - ERAM_START, new_byte),
             WRAM_START..=WRAM_END => self.wram[(address - WRAM_START) as usize] = new_byte,
             ECHO_START..=ECHO_END => self.wram[(address - ECHO_START) as usize] = new_byte,
             // OAM_START..=OAM_END => self.gpu.oam[(address - OAM_START) as usize] = new_byte,
-            OAM_START..=OAM_END => self.gpu.set_oam(address - VRAM_START, new_byte),
+            OAM_START..=OAM_END => self.gpu.set_oam(address - OAM_START, new_byte),
+            UNUSABLE_START..=UNUSABLE_END => {}
             IO_START..=IO_END => self.write_io_register(address, new_byte),
             HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize] = new_byte,
-            _ => panic!("Cannot access address 0x{}", address)
+            IE_REGISTER => self.interrupt_enable = new_byte,
         };
     }
 
-    // TODO
+    // dispatch a read from 0xff00-0xff7f to the component that owns the register
+    //
+    // registers that don't exist on the dmg read as 0xff
     fn read_io_register(&self, address: u16) -> u8 {
-        0
+        match address {
+            P1_REGISTER => self.joypad.read(),
+            SB_REGISTER => self.serial.read_data(),
+            SC_REGISTER => self.serial.read_control(),
+            DIV_REGISTER => self.timer.read_div(),
+            TIMA_REGISTER => self.timer.read_tima(),
+            TMA_REGISTER => self.timer.read_tma(),
+            TAC_REGISTER => self.timer.read_tac(),
+            // only the lower 5 bits exist, the rest read back as 1
+            IF_REGISTER => self.interrupt_flag | 0xe0,
+            NR10_REGISTER..=WAVE_RAM_END => self.apu.read_register(address),
+            DMA_REGISTER => self.dma,
+            LCDC_REGISTER..=STAT_REGISTER | SCY_REGISTER..=LYC_REGISTER | BGP_REGISTER..=WX_REGISTER => {
+                self.gpu.read_register(address)
+            }
+            _ => 0xff,
+        }
     }
 
-    // TODO
+    // dispatch a write to 0xff00-0xff7f to the component that owns the register
     fn write_io_register(&mut self, address: u16, new_byte: u8) {
+        match address {
+            P1_REGISTER => self.joypad.write(new_byte),
+            SB_REGISTER => self.serial.write_data(new_byte),
+            SC_REGISTER => self.serial.write_control(new_byte),
+            DIV_REGISTER => self.timer.write_div(),
+            TIMA_REGISTER => self.timer.write_tima(new_byte),
+            TMA_REGISTER => self.timer.write_tma(new_byte),
+            TAC_REGISTER => self.timer.write_tac(new_byte),
+            IF_REGISTER => self.interrupt_flag = new_byte & 0x1f,
+            NR10_REGISTER..=WAVE_RAM_END => self.apu.write_register(address, new_byte),
+            // the boot rom unmaps itself as its last instruction, and can't be mapped back in
+            BOOT_REGISTER if new_byte != 0 => self.bios_mapped = false,
+            // writing restarts the transfer, even if one is already running
+            DMA_REGISTER => {
+                self.dma = new_byte;
+                self.dma_progress = Some(0);
+            }
+            LCDC_REGISTER..=STAT_REGISTER | SCY_REGISTER..=LYC_REGISTER | BGP_REGISTER..=WX_REGISTER => {
+                self.gpu.write_register(address, new_byte)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cartridge_is_visible_at_0x0000_without_a_boot_rom() {
+        let mut game = vec![0; 0x8000];
+        game[0x0000] = 0xc3;
+        game[0x0040] = 0xd9;
+
+        let bus = MemoryBus::new(Vec::new(), game.clone()).unwrap();
+        assert_eq!(bus.read_byte(0x0000), 0xc3);
+        assert_eq!(bus.read_byte(0x0040), 0xd9);
+
+        // with one, it covers the cartridge until 0xff50 is written
+        let mut bus = MemoryBus::new(vec![0x31; 0x100], game).unwrap();
+        assert_eq!(bus.read_byte(0x0000), 0x31);
+        bus.set_byte(BOOT_REGISTER, 0x01);
+        assert_eq!(bus.read_byte(0x0000), 0xc3);
+    }
+
+    #[test]
+    fn writing_dma_during_a_transfer_restarts_it() {
+        let mut bus = MemoryBus::new(Vec::new(), vec![0; 0x8000]).unwrap();
+        for i in 0..DMA_LENGTH {
+            bus.set_byte(0xc000 + i, 0x11);
+            bus.set_byte(0xc100 + i, 0x22);
+        }
+
+        bus.set_byte(DMA_REGISTER, 0xc0);
+        for _ in 0..DMA_LENGTH / 2 {
+            bus.step(4);
+        }
+
+        // the second transfer starts from the beginning and copies everything again
+        bus.set_byte(DMA_REGISTER, 0xc1);
+        for _ in 0..DMA_LENGTH {
+            bus.step(4);
+        }
+
+        assert_eq!(bus.dma_progress, None);
+        for i in 0..DMA_LENGTH {
+            assert_eq!(bus.gpu.read_oam(i), 0x22);
+        }
+    }
 
+    #[test]
+    fn boot_rom_must_be_256_bytes() {
+        assert!(MemoryBus::new(vec![0; 0x101], Vec::new()).is_err());
+        assert!(MemoryBus::new(vec![0; 0xff], Vec::new()).is_err());
     }
 }
@@ -1,3 +1,6 @@
+// the whole map is kept, including the bounds nothing happens to need
+#![allow(dead_code)]
+
 pub const BIOS_START: u16 = 0x00;
 pub const BIOS_END: u16 = 0xff;
 
@@ -22,10 +25,65 @@ pub const ECHO_END: u16 = 0xfdff;
 pub const OAM_START: u16 = 0xfe00;
 pub const OAM_END: u16 = 0xfe9f;
 
+pub const UNUSABLE_START: u16 = 0xfea0;
+pub const UNUSABLE_END: u16 = 0xfeff;
+
 pub const IO_START: u16 = 0xff00;
 pub const IO_END: u16 = 0xff7f;
 
 pub const HRAM_START: u16 = 0xff80;
 pub const HRAM_END: u16 = 0xfffe;
 
+pub const P1_REGISTER: u16 = 0xff00;
+
+pub const SB_REGISTER: u16 = 0xff01;
+pub const SC_REGISTER: u16 = 0xff02;
+
+pub const DIV_REGISTER: u16 = 0xff04;
+pub const TIMA_REGISTER: u16 = 0xff05;
+pub const TMA_REGISTER: u16 = 0xff06;
+pub const TAC_REGISTER: u16 = 0xff07;
+
+pub const IF_REGISTER: u16 = 0xff0f;
+
+pub const NR10_REGISTER: u16 = 0xff10;
+pub const NR11_REGISTER: u16 = 0xff11;
+pub const NR12_REGISTER: u16 = 0xff12;
+pub const NR13_REGISTER: u16 = 0xff13;
+pub const NR14_REGISTER: u16 = 0xff14;
+pub const NR21_REGISTER: u16 = 0xff16;
+pub const NR22_REGISTER: u16 = 0xff17;
+pub const NR23_REGISTER: u16 = 0xff18;
+pub const NR24_REGISTER: u16 = 0xff19;
+pub const NR30_REGISTER: u16 = 0xff1a;
+pub const NR31_REGISTER: u16 = 0xff1b;
+pub const NR32_REGISTER: u16 = 0xff1c;
+pub const NR33_REGISTER: u16 = 0xff1d;
+pub const NR34_REGISTER: u16 = 0xff1e;
+pub const NR41_REGISTER: u16 = 0xff20;
+pub const NR42_REGISTER: u16 = 0xff21;
+pub const NR43_REGISTER: u16 = 0xff22;
+pub const NR44_REGISTER: u16 = 0xff23;
+pub const NR50_REGISTER: u16 = 0xff24;
+pub const NR51_REGISTER: u16 = 0xff25;
+pub const NR52_REGISTER: u16 = 0xff26;
+
+pub const WAVE_RAM_START: u16 = 0xff30;
+pub const WAVE_RAM_END: u16 = 0xff3f;
+
+pub const LCDC_REGISTER: u16 = 0xff40;
+pub const STAT_REGISTER: u16 = 0xff41;
+pub const SCY_REGISTER: u16 = 0xff42;
+pub const SCX_REGISTER: u16 = 0xff43;
+pub const LY_REGISTER: u16 = 0xff44;
+pub const LYC_REGISTER: u16 = 0xff45;
+pub const DMA_REGISTER: u16 = 0xff46;
+pub const BGP_REGISTER: u16 = 0xff47;
+pub const OBP0_REGISTER: u16 = 0xff48;
+pub const OBP1_REGISTER: u16 = 0xff49;
+pub const WY_REGISTER: u16 = 0xff4a;
+pub const WX_REGISTER: u16 = 0xff4b;
+
+pub const BOOT_REGISTER: u16 = 0xff50;
+
 pub const IE_REGISTER: u16 = 0xffff;
@@ -0,0 +1,284 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::png;
+use crate::serial::SerialDevice;
+
+// every packet starts with these two bytes
+const MAGIC: [u8; 2] = [0x88, 0x33];
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0f;
+
+// what the printer answers with after the checksum, so the game knows it's there
+const ALIVE: u8 = 0x81;
+
+// bits of the status byte
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_DATA_FULL: u8 = 0x04;
+const UNPROCESSED_DATA: u8 = 0x08;
+
+// the printer has 8KiB of ram for the image
+const BUFFER_SIZE: usize = 0x2000;
+
+// the paper is 160 pixels wide, so a row of tiles is 20 tiles of 16 bytes
+const WIDTH: usize = 160;
+const TILE_ROW_SIZE: usize = 20 * 16;
+
+// games poll the status while the printer is busy, and wait for it to finish
+const PRINTING_POLLS: u8 = 4;
+
+// how each shade of the printed image comes out in the png
+const GREYS: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Magic,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// the game boy printer, plugged into the link port
+//
+// the game is always the clock master, sending packets of
+// magic, command, compression, length, data, checksum and two bytes of padding,
+// the printer only answers on the padding with 0x81 and then its status
+//
+// every print writes the image received so far out as a png into the directory
+pub struct Printer {
+    directory: PathBuf,
+    // the paths of the images printed so far
+    printed: Vec<PathBuf>,
+    state: State,
+    // how many of the magic bytes have lined up so far
+    position: usize,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    image: Vec<u8>,
+    status: u8,
+    printing_polls: u8,
+}
+
+impl Printer {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Printer {
+        Printer {
+            directory: directory.as_ref().to_path_buf(),
+            printed: Vec::new(),
+            state: State::Magic,
+            position: 0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            image: Vec::new(),
+            status: 0,
+            printing_polls: 0,
+        }
+    }
+
+    pub fn printed(&self) -> &[PathBuf] {
+        &self.printed
+    }
+
+    // the whole packet has arrived, act on it
+    fn handle_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            }
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+
+                let space = BUFFER_SIZE - self.image.len();
+                self.image.extend(data.into_iter().take(space));
+
+                if !self.image.is_empty() {
+                    self.status |= UNPROCESSED_DATA;
+                }
+                if self.image.len() == BUFFER_SIZE {
+                    self.status |= IMAGE_DATA_FULL;
+                }
+            }
+            PRINT if self.data.len() >= 4 => {
+                // sheets, margins, palette then exposure, only the palette changes the image
+                self.print(self.data[2]);
+                self.image.clear();
+                self.status = (self.status & !(UNPROCESSED_DATA | IMAGE_DATA_FULL)) | PRINTING;
+                self.printing_polls = PRINTING_POLLS;
+            }
+            // the print finishes after the game has checked on it a few times
+            STATUS if self.printing_polls > 0 => {
+                self.printing_polls -= 1;
+                if self.printing_polls == 0 {
+                    self.status &= !PRINTING;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // write out the image received so far as a png
+    fn print(&mut self, palette: u8) {
+        let rows = self.image.len() / TILE_ROW_SIZE;
+        if rows == 0 {
+            return;
+        }
+
+        // games often leave the palette at 0 to mean the usual one
+        let palette = if palette == 0 { 0xe4 } else { palette };
+
+        let height = rows * 8;
+        let mut pixels = vec![0; WIDTH * height];
+
+        for (i, tile) in self.image[..rows * TILE_ROW_SIZE].chunks(16).enumerate() {
+            let top = i / 20 * 8;
+            let left = i % 20 * 8;
+
+            for y in 0..8 {
+                let (low, high) = (tile[y * 2], tile[y * 2 + 1]);
+
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    let colour = ((high >> bit) & 0x1) << 1 | ((low >> bit) & 0x1);
+                    let shade = (palette >> (colour * 2)) & 0x3;
+                    pixels[(top + y) * WIDTH + left + x] = GREYS[shade as usize];
+                }
+            }
+        }
+
+        let png = png::encode_greyscale(WIDTH as u32, height as u32, &pixels);
+
+        // find a name that hasn't been used, so earlier prints are never overwritten
+        let path = (self.printed.len() + 1..)
+            .map(|n| self.directory.join(format!("print_{:04}.png", n)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        // a failed print shouldn't take the game down with it
+        match fs::create_dir_all(&self.directory).and_then(|_| fs::write(&path, png)) {
+            Ok(()) => self.printed.push(path),
+            Err(error) => eprintln!("could not print to {}: {}", path.display(), error),
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+
+        match self.state {
+            State::Magic => {
+                // keep looking for the start of a packet until both magic bytes line up
+                if byte == MAGIC[self.position] {
+                    self.position += 1;
+                } else {
+                    self.position = if byte == MAGIC[0] { 1 } else { 0 };
+                }
+
+                if self.position == MAGIC.len() {
+                    self.state = State::Command;
+                }
+            }
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 0x1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthLow;
+            }
+            State::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                };
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length {
+                    self.state = State::ChecksumLow;
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                self.state = State::ChecksumHigh;
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.handle_packet();
+                self.state = State::Alive;
+            }
+            State::Alive => {
+                reply = ALIVE;
+                self.state = State::Status;
+            }
+            State::Status => {
+                reply = self.status;
+                self.position = 0;
+                self.state = State::Magic;
+            }
+        }
+
+        reply
+    }
+}
+
+// undo the run length encoding of compressed data packets
+//
+// a control byte with the top bit clear is followed by that many plus one bytes to copy,
+// with it set the next byte is repeated the lower bits plus two times
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+
+    while let Some(&control) = bytes.next() {
+        if control & 0x80 == 0 {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        } else if let Some(&byte) = bytes.next() {
+            output.resize(output.len() + (control & 0x7f) as usize + 2, byte);
+        }
+    }
+
+    output
+}
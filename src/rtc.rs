@@ -0,0 +1,404 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the rtc registers selected by writing 0x08-0x0c to the mbc3 ram bank register
+pub const RTC_SECONDS: u8 = 0x08;
+pub const RTC_MINUTES: u8 = 0x09;
+pub const RTC_HOURS: u8 = 0x0a;
+pub const RTC_DAY_LOW: u8 = 0x0b;
+pub const RTC_DAY_HIGH: u8 = 0x0c;
+
+// bits of the day high register
+const DAY_HIGH_BIT: u8 = 0x01;
+const HALT_BIT: u8 = 0x40;
+const DAY_CARRY_BIT: u8 = 0x80;
+
+// the day counter is 9 bits, past that it wraps and sets the carry
+const DAYS: u64 = 0x200;
+
+// the common bgb/vba format, 5 registers then the same 5 latched, each as a
+// little endian u32, followed by a 64 bit unix timestamp
+pub const RTC_SAVE_SIZE: usize = 48;
+// older saves only have a 32 bit timestamp
+const RTC_SAVE_SIZE_SHORT: usize = 44;
+
+// where the rtc gets the time from, in seconds since the unix epoch
+//
+// the system clock is used normally, tests can swap in one they control
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// the mbc3 real time clock
+//
+// rather than ticking every second, the registers are stored along with the time they
+// were set, and worked out from the elapsed time whenever they're needed
+pub struct Rtc {
+    clock: Box<dyn Clock>,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    // when the registers above were last brought up to date
+    timestamp: u64,
+    // the game reads a snapshot of the registers, taken when it writes 0 then 1 to 0x6000-0x7fff
+    latched: [u8; 5],
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Rtc {
+        let timestamp = clock.now();
+
+        Rtc {
+            clock,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            timestamp,
+            latched: [0; 5],
+            latch_armed: false,
+        }
+    }
+
+    // change where the time comes from, keeping the registers as they are now
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.sync();
+        self.clock = clock;
+        self.timestamp = self.clock.now();
+    }
+
+    // read one of the latched registers, 0x08-0x0c
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - RTC_SECONDS) as usize]
+    }
+
+    // write one of the registers, 0x08-0x0c
+    //
+    // writes go to the running clock, not the latched copy
+    pub fn write(&mut self, register: u8, new_byte: u8) {
+        self.sync();
+
+        match register {
+            RTC_SECONDS => self.seconds = new_byte & 0x3f,
+            RTC_MINUTES => self.minutes = new_byte & 0x3f,
+            RTC_HOURS => self.hours = new_byte & 0x1f,
+            RTC_DAY_LOW => self.days = (self.days & 0x100) | new_byte as u16,
+            RTC_DAY_HIGH => {
+                self.days = (self.days & 0xff) | ((new_byte & DAY_HIGH_BIT) as u16) << 8;
+                self.halted = new_byte & HALT_BIT != 0;
+                self.day_carry = new_byte & DAY_CARRY_BIT != 0;
+            }
+            _ => {}
+        }
+    }
+
+    // writes to 0x6000-0x7fff, a 0 followed by a 1 copies the clock into the latched registers
+    pub fn write_latch(&mut self, new_byte: u8) {
+        if self.latch_armed && new_byte == 0x01 {
+            self.latched = self.registers();
+        }
+
+        self.latch_armed = new_byte == 0x00;
+    }
+
+    // the clock state to append to the .sav file
+    pub fn save_data(&self) -> [u8; RTC_SAVE_SIZE] {
+        let mut data = [0; RTC_SAVE_SIZE];
+
+        let registers = self.registers();
+        let values = registers.iter().chain(self.latched.iter());
+        for (i, &value) in values.enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+
+        data[40..48].copy_from_slice(&self.clock.now().to_le_bytes());
+
+        data
+    }
+
+    // restore the clock from the end of a .sav file
+    //
+    // the registers are taken to be as they were at the saved timestamp, so the time
+    // the emulator was closed is caught up on as soon as they're next read
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE_SHORT {
+            return;
+        }
+
+        let value = |i: usize| data[i * 4];
+
+        self.seconds = value(0) & 0x3f;
+        self.minutes = value(1) & 0x3f;
+        self.hours = value(2) & 0x1f;
+        self.days = (value(3) as u16) | ((value(4) & DAY_HIGH_BIT) as u16) << 8;
+        self.halted = value(4) & HALT_BIT != 0;
+        self.day_carry = value(4) & DAY_CARRY_BIT != 0;
+
+        for i in 0..5 {
+            self.latched[i] = value(i + 5);
+        }
+
+        let mut timestamp = [0; 8];
+        if data.len() >= RTC_SAVE_SIZE {
+            timestamp.copy_from_slice(&data[40..48]);
+        } else {
+            timestamp[..4].copy_from_slice(&data[40..44]);
+        }
+        self.timestamp = u64::from_le_bytes(timestamp);
+    }
+
+    // the registers as they are right now, in the order 0x08-0x0c
+    fn registers(&self) -> [u8; 5] {
+        let (seconds, minutes, hours, days, day_carry) = self.advance();
+
+        let mut day_high = ((days >> 8) as u8) & DAY_HIGH_BIT;
+        if self.halted {
+            day_high |= HALT_BIT;
+        }
+        if day_carry {
+            day_high |= DAY_CARRY_BIT;
+        }
+
+        [seconds, minutes, hours, days as u8, day_high]
+    }
+
+    // work out what the registers have counted up to since the timestamp
+    fn advance(&self) -> (u8, u8, u8, u16, bool) {
+        let elapsed = self.clock.now().saturating_sub(self.timestamp);
+
+        if self.halted || elapsed == 0 {
+            return (self.seconds, self.minutes, self.hours, self.days, self.day_carry);
+        }
+
+        let seconds = self.seconds as u64 + elapsed;
+        let minutes = self.minutes as u64 + seconds / 60;
+        let hours = self.hours as u64 + minutes / 60;
+        let days = self.days as u64 + hours / 24;
+
+        (
+            (seconds % 60) as u8,
+            (minutes % 60) as u8,
+            (hours % 24) as u8,
+            (days % DAYS) as u16,
+            // once set, the carry stays set until the game clears it
+            self.day_carry || days >= DAYS,
+        )
+    }
+
+    // bring the stored registers up to date, so they can be changed
+    fn sync(&mut self) {
+        let (seconds, minutes, hours, days, day_carry) = self.advance();
+
+        self.seconds = seconds;
+        self.minutes = minutes;
+        self.hours = hours;
+        self.days = days;
+        self.day_carry = day_carry;
+        self.timestamp = self.clock.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::convert::TryInto;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::emulator::{test_emulator, Emulator};
+
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    // a clock that only moves when the test moves it
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<u64>>);
+
+    impl FakeClock {
+        fn new(now: u64) -> FakeClock {
+            FakeClock(Rc::new(Cell::new(now)))
+        }
+
+        fn advance(&self, seconds: u64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    fn latched(rtc: &Rtc) -> [u8; 5] {
+        let mut registers = [0; 5];
+        for (i, register) in (RTC_SECONDS..=RTC_DAY_HIGH).enumerate() {
+            registers[i] = rtc.read(register);
+        }
+        registers
+    }
+
+    #[test]
+    fn latches_only_on_zero_then_one() {
+        let clock = FakeClock::new(1000);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+        clock.advance(5);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+
+        // a 1 without the 0 just before it doesn't count
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x02);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+
+        // the latched copy stays put while the clock keeps running
+        clock.advance(10);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_SECONDS), 15);
+    }
+
+    #[test]
+    fn rolls_over_into_minutes_hours_and_days() {
+        let clock = FakeClock::new(0);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+        clock.advance(59);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [59, 0, 0, 0, 0]);
+
+        clock.advance(1);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 1, 0, 0, 0]);
+
+        clock.advance(59 * MINUTE);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 0, 1, 0, 0]);
+
+        clock.advance(23 * HOUR + MINUTE + 1);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [1, 1, 0, 1, 0]);
+
+        // the ninth bit of the day counter lives in day high
+        clock.advance(255 * DAY);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [1, 1, 0, 0, DAY_HIGH_BIT]);
+    }
+
+    #[test]
+    fn day_counter_wraps_and_sets_the_carry() {
+        let clock = FakeClock::new(0);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+        clock.advance(511 * DAY);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 0, 0, 0xff, DAY_HIGH_BIT]);
+
+        clock.advance(DAY);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 0, 0, 0, DAY_CARRY_BIT]);
+
+        // the carry sticks until the game clears it
+        clock.advance(DAY);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 0, 0, 1, DAY_CARRY_BIT]);
+
+        rtc.write(RTC_DAY_HIGH, 0x00);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn halt_freezes_time() {
+        let clock = FakeClock::new(0);
+        let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+        clock.advance(10);
+        rtc.write(RTC_DAY_HIGH, HALT_BIT);
+        clock.advance(HOUR);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [10, 0, 0, 0, HALT_BIT]);
+
+        // picks up where it stopped, without counting the time it was halted
+        rtc.write(RTC_DAY_HIGH, 0x00);
+        clock.advance(5);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [15, 0, 0, 0, 0]);
+    }
+
+    // an mbc3 cartridge with a timer and battery, but no ram, so the save is just the clock
+    fn timer_cartridge(clock: &FakeClock) -> Emulator {
+        let mut emulator = test_emulator(0x0f, &[]);
+        emulator.set_rtc_clock(Box::new(clock.clone()));
+        emulator
+    }
+
+    #[test]
+    fn save_round_trip_catches_up_on_time_spent_closed() {
+        let clock = FakeClock::new(1_000_000);
+        let emulator = timer_cartridge(&clock);
+
+        clock.advance(HOUR + 2 * MINUTE + 3);
+        let save = emulator.save_data().unwrap();
+        assert_eq!(save.len(), RTC_SAVE_SIZE);
+        assert_eq!(&save[..20], &[3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(u64::from_le_bytes(save[40..48].try_into().unwrap()), clock.now());
+
+        // the emulator is closed for a day and a bit before the game starts up again
+        clock.advance(DAY + 10);
+        let mut reloaded = timer_cartridge(&clock);
+        reloaded.load_save_data(&save);
+
+        let resaved = reloaded.save_data().unwrap();
+        assert_eq!(&resaved[..20], &[13, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn loads_saves_with_a_short_timestamp() {
+        let clock = FakeClock::new(2_000_000);
+
+        // 5 registers, the same 5 latched, then a 32 bit timestamp from a minute ago
+        let mut save = Vec::new();
+        for &value in [30, 10, 5, 2, 0].iter().chain([30, 10, 5, 2, 0].iter()) {
+            save.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        save.extend_from_slice(&((clock.now() - MINUTE) as u32).to_le_bytes());
+        assert_eq!(save.len(), RTC_SAVE_SIZE_SHORT);
+
+        let mut emulator = timer_cartridge(&clock);
+        emulator.load_save_data(&save);
+
+        let resaved = emulator.save_data().unwrap();
+        assert_eq!(&resaved[..20], &[30, 0, 0, 0, 11, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        // the latched registers come back as they were saved
+        assert_eq!(&resaved[20..40], &save[20..40]);
+    }
+}
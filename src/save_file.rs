@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Emulator;
+
+// how many frames to wait between writing changed save data, about once a second
+const FLUSH_INTERVAL: u32 = 60;
+
+// keeps battery backed cartridge ram in sync with a .sav file next to the rom
+pub struct SaveFile {
+    path: PathBuf,
+    frames_since_flush: u32,
+}
+
+impl SaveFile {
+    // the save for a rom lives beside it, with the extension swapped for .sav
+    pub fn for_rom(rom_path: &Path) -> SaveFile {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            frames_since_flush: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // load the save into the cartridge, if the game has a battery and a save exists yet
+    pub fn load(&self, emulator: &mut Emulator) -> io::Result<()> {
+        if !emulator.has_battery() || !self.path.exists() {
+            return Ok(());
+        }
+
+        let data = fs::read(&self.path)?;
+        emulator.load_save_data(&data);
+
+        Ok(())
+    }
+
+    // write the save out if it has changed since the last flush
+    pub fn flush(&mut self, emulator: &mut Emulator) -> io::Result<()> {
+        self.frames_since_flush = 0;
+
+        if !emulator.save_data_modified() {
+            return Ok(());
+        }
+
+        match emulator.save_data() {
+            Some(data) => fs::write(&self.path, data),
+            None => Ok(()),
+        }
+    }
+
+    // call once per frame, flushing periodically so a crash or kill doesn't lose much
+    pub fn frame(&mut self, emulator: &mut Emulator) -> io::Result<()> {
+        self.frames_since_flush += 1;
+
+        if self.frames_since_flush >= FLUSH_INTERVAL {
+            self.flush(emulator)
+        } else {
+            Ok(())
+        }
+    }
+}
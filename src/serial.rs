@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::interrupts::Interrupt;
+
+// with the internal clock bits go out at 8192Hz, so a whole byte takes 8 * 512 cycles
+const TRANSFER_CYCLES: u16 = 4096;
+
+// bits of SC
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+
+// whatever is plugged into the link port
+//
+// the console shifts its byte out while shifting the other side's byte in,
+// so every transfer is an exchange of one byte each way
+pub trait SerialDevice {
+    // the console has clocked out a whole byte with its internal clock,
+    // returns the byte that was shifted in at the same time
+    //
+    // that can be one the other side already clocked in, if both started a transfer at once
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    // polled while the console is waiting on an external clock,
+    // returns the byte shifted in once the other side has clocked a transfer
+    //
+    // nothing drives the clock by default, so the console waits forever like real hardware
+    fn external_transfer(&mut self) -> Option<u8> {
+        None
+    }
+
+    // called first thing every time the console steps, for devices that need to keep time with it
+    //
+    // `ready` is the byte the console has ready to go once a transfer has been started, with
+    // either clock, so a device that clocks it knows what it would get back
+    fn step(&mut self, _cycles: u8, _ready: Option<u8>) {}
+}
+
+// the default device, with nothing plugged in
+//
+// bytes sent out are kept so the output of test roms that report over serial can be read back,
+// and can also be echoed to stdout as they arrive
+pub struct SerialCapture {
+    output: Rc<RefCell<Vec<u8>>>,
+    echo: bool,
+}
+
+impl SerialCapture {
+    pub fn new() -> SerialCapture {
+        SerialCapture {
+            output: Rc::new(RefCell::new(Vec::new())),
+            echo: false,
+        }
+    }
+
+    // capture and also print every byte to stdout
+    pub fn stdout() -> SerialCapture {
+        SerialCapture {
+            echo: true,
+            ..SerialCapture::new()
+        }
+    }
+
+    // a handle to the captured bytes, which stays valid once the device is plugged in
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl Default for SerialCapture {
+    fn default() -> SerialCapture {
+        SerialCapture::new()
+    }
+}
+
+impl SerialDevice for SerialCapture {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.output.borrow_mut().push(byte);
+
+        if self.echo {
+            let mut stdout = io::stdout();
+            // losing output is better than stopping the game over it
+            let _ = stdout.write_all(&[byte]);
+            let _ = stdout.flush();
+        }
+
+        // with no cable connected the line is pulled high
+        0xff
+    }
+}
+
+pub struct Serial {
+    device: Box<dyn SerialDevice>,
+    // SB, the byte being sent, which is replaced by the byte received
+    data: u8,
+    // SC, whether a transfer is running and which side provides the clock
+    control: u8,
+    // how far through the current internal clock transfer we are
+    clock: u16,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            device: Box::new(SerialCapture::new()),
+            data: 0,
+            control: 0,
+            clock: 0,
+        }
+    }
+
+    // plug something else into the link port
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    // unplug whatever is in the link port, leaving it empty
+    pub fn take_device(&mut self) -> Box<dyn SerialDevice> {
+        mem::replace(&mut self.device, Box::new(SerialCapture::new()))
+    }
+
+    // advance the serial port by the number of cycles the cpu just took
+    //
+    // returns the serial interrupt in the same layout as IF when a transfer finishes
+    pub fn step(&mut self, cycles: u8) -> u8 {
+        let ready = if self.control & TRANSFER_START != 0 {
+            Some(self.data)
+        } else {
+            None
+        };
+        self.device.step(cycles, ready);
+
+        if self.control & TRANSFER_START == 0 {
+            return 0;
+        }
+
+        let received = if self.control & INTERNAL_CLOCK != 0 {
+            self.clock += cycles as u16;
+            if self.clock < TRANSFER_CYCLES {
+                return 0;
+            }
+
+            self.device.transfer(self.data)
+        } else {
+            match self.device.external_transfer() {
+                Some(byte) => byte,
+                None => return 0,
+            }
+        };
+
+        self.data = received;
+        self.control &= !TRANSFER_START;
+        Interrupt::Serial.mask()
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data
+    }
+
+    // the unused bits of SC read as 1
+    pub fn read_control(&self) -> u8 {
+        self.control | 0x7e
+    }
+
+    pub fn write_data(&mut self, new_byte: u8) {
+        self.data = new_byte;
+    }
+
+    pub fn write_control(&mut self, new_byte: u8) {
+        self.control = new_byte & (TRANSFER_START | INTERNAL_CLOCK);
+        self.clock = 0;
+    }
+}
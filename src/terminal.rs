@@ -0,0 +1,199 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::emulator::Emulator;
+use crate::frontend::{FramePacer, BUTTONS, PALETTE};
+use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::joypad::Button;
+
+// terminals only say when a key is pressed, not when it's let go,
+// so a button is held for this many frames after its key was last seen
+const HOLD_FRAMES: u8 = 8;
+
+// what a key read from the terminal does
+enum Key {
+    Button(Button),
+    Quit,
+}
+
+// a front end that draws into an ansi truecolor terminal, for running over ssh
+//
+// each character cell shows two pixels, an upper half block coloured with the top pixel
+// and a background of the bottom one. the arrow keys (or wasd) are the d-pad, x and z are
+// A and B, enter is start, space or backspace is select, and q or ctrl-c quits
+//
+// the terminal is put into raw mode while this is alive, and put back when it's dropped
+pub struct Terminal {
+    keys: Receiver<u8>,
+    // bytes of an escape sequence read so far
+    escape: Vec<u8>,
+    // frames left before each button is let go, in the order of BUTTONS
+    held: [u8; 8],
+    // the settings to restore, as given by stty -g
+    saved_settings: String,
+    pacer: FramePacer,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Terminal> {
+        let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output()?;
+        if !saved.status.success() {
+            return Err(io::Error::other("stdin is not a terminal"));
+        }
+        stty(&["raw", "-echo"])?;
+
+        // reading stdin blocks, so it's done on its own thread
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut buffer = [0; 64];
+
+            while let Ok(length) = stdin.read(&mut buffer) {
+                if length == 0 || buffer[..length].iter().any(|&byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        // switch to the alternate screen, and hide the cursor
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+
+        Ok(Terminal {
+            keys,
+            escape: Vec::new(),
+            held: [0; 8],
+            saved_settings: String::from_utf8_lossy(&saved.stdout).trim().to_string(),
+            pacer: FramePacer::new(),
+        })
+    }
+
+    // pass on the keys pressed since the last frame to the emulator
+    //
+    // returns false once the player wants to quit
+    pub fn update_input(&mut self, emulator: &mut Emulator) -> bool {
+        for held in self.held.iter_mut() {
+            *held = held.saturating_sub(1);
+        }
+
+        while let Ok(byte) = self.keys.try_recv() {
+            match self.decode(byte) {
+                Some(Key::Quit) => return false,
+                Some(Key::Button(button)) => {
+                    let i = BUTTONS.iter().position(|&b| b == button).unwrap();
+                    self.held[i] = HOLD_FRAMES;
+                }
+                None => {}
+            }
+        }
+
+        for (&button, &held) in BUTTONS.iter().zip(self.held.iter()) {
+            emulator.set_button(button, held > 0);
+        }
+
+        true
+    }
+
+    // draw a frame of shades, as given by Emulator::framebuffer
+    pub fn draw(&mut self, framebuffer: &[u8]) -> io::Result<()> {
+        // move to the top left rather than clearing, so the screen doesn't flicker
+        let mut output = String::from("\x1b[H");
+        let mut colours = None;
+
+        for y in (0..SCREEN_HEIGHT).step_by(2) {
+            for x in 0..SCREEN_WIDTH {
+                let top = framebuffer[y * SCREEN_WIDTH + x];
+                let bottom = framebuffer[(y + 1) * SCREEN_WIDTH + x];
+
+                // only send colours when they change, which keeps frames small
+                if colours != Some((top, bottom)) {
+                    let (tr, tg, tb) = PALETTE[top as usize];
+                    let (br, bg, bb) = PALETTE[bottom as usize];
+                    output += &format!("\x1b[38;2;{};{};{};48;2;{};{};{}m", tr, tg, tb, br, bg, bb);
+                    colours = Some((top, bottom));
+                }
+
+                output.push('\u{2580}');
+            }
+
+            // no newline after the last row, so a terminal exactly tall enough doesn't scroll
+            output += "\x1b[0m";
+            if y + 2 < SCREEN_HEIGHT {
+                output += "\r\n";
+            }
+            colours = None;
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()
+    }
+
+    // sleep until it's time for the next frame, so the game runs at the real ~59.7Hz
+    pub fn wait_for_next_frame(&mut self) {
+        self.pacer.wait(1);
+    }
+
+    // turn bytes from stdin into keys, following escape sequences for the arrow keys
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        if !self.escape.is_empty() || byte == 0x1b {
+            self.escape.push(byte);
+
+            // arrows are sent as ESC [ A-D, or ESC O A-D in application mode
+            if self.escape.len() < 3 {
+                if self.escape.len() == 2 && byte != b'[' && byte != b'O' {
+                    self.escape.clear();
+                }
+                return None;
+            }
+
+            self.escape.clear();
+            return match byte {
+                b'A' => Some(Key::Button(Button::Up)),
+                b'B' => Some(Key::Button(Button::Down)),
+                b'C' => Some(Key::Button(Button::Right)),
+                b'D' => Some(Key::Button(Button::Left)),
+                _ => None,
+            };
+        }
+
+        match byte {
+            b'w' => Some(Key::Button(Button::Up)),
+            b's' => Some(Key::Button(Button::Down)),
+            b'a' => Some(Key::Button(Button::Left)),
+            b'd' => Some(Key::Button(Button::Right)),
+            b'x' => Some(Key::Button(Button::A)),
+            b'z' => Some(Key::Button(Button::B)),
+            b'\r' | b'\n' => Some(Key::Button(Button::Start)),
+            b' ' | 0x7f | 0x08 => Some(Key::Button(Button::Select)),
+            // ctrl-c doesn't interrupt in raw mode, so it's handled here
+            b'q' | 0x03 => Some(Key::Quit),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // show the cursor again and go back to the normal screen
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+
+        let _ = stty(&[self.saved_settings.as_str()]);
+    }
+}
+
+// change the settings of the terminal on stdin
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty").args(args).stdin(Stdio::inherit()).status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("stty failed"))
+    }
+}
@@ -0,0 +1,115 @@
+use crate::interrupts::Interrupt;
+
+// the bit of the internal counter that clocks TIMA for each TAC clock select,
+// i.e. TIMA goes up every 1024, 16, 64 or 256 cycles
+const CLOCK_SELECT_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+pub struct Timer {
+    // the internal 16 bit counter, which goes up every cycle
+    // DIV is just the upper byte of it
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA reads as 0 for one m-cycle after overflowing before TMA is loaded into it
+    overflow_pending: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow_pending: false,
+        }
+    }
+
+    // advance the timer by the number of cycles the cpu just took
+    //
+    // returns the interrupts requested along the way, in the same layout as IF
+    pub fn step(&mut self, cycles: u8) -> u8 {
+        let mut interrupts = 0;
+
+        // the timer works in m-cycles (4 cycles) so handle them one at a time
+        for _ in 0..cycles / 4 {
+            if self.overflow_pending {
+                self.overflow_pending = false;
+                self.tima = self.tma;
+                interrupts |= Interrupt::Timer.mask();
+            }
+
+            self.set_counter(self.counter.wrapping_add(4));
+        }
+
+        interrupts
+    }
+
+    pub fn read_div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    pub fn read_tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn read_tma(&self) -> u8 {
+        self.tma
+    }
+
+    pub fn read_tac(&self) -> u8 {
+        // only the lower 3 bits exist
+        self.tac | 0xf8
+    }
+
+    // any write to DIV clears the whole internal counter, which can
+    // make TIMA tick early if the selected bit was set
+    pub fn write_div(&mut self) {
+        self.set_counter(0);
+    }
+
+    pub fn write_tima(&mut self, new_byte: u8) {
+        // writing during the cycle after an overflow cancels the reload and the interrupt
+        self.overflow_pending = false;
+        self.tima = new_byte;
+    }
+
+    pub fn write_tma(&mut self, new_byte: u8) {
+        self.tma = new_byte;
+    }
+
+    // disabling the timer or switching clocks can also cause a falling edge, and tick TIMA
+    pub fn write_tac(&mut self, new_byte: u8) {
+        let old_input = self.input();
+        self.tac = new_byte & 0x7;
+
+        if old_input && !self.input() {
+            self.increment_tima();
+        }
+    }
+
+    // TIMA is clocked by the selected counter bit anded with the enable bit,
+    // and ticks whenever that signal goes from high to low
+    fn input(&self) -> bool {
+        self.tac & 0x4 != 0 && self.counter & CLOCK_SELECT_BITS[(self.tac & 0x3) as usize] != 0
+    }
+
+    pub fn set_counter(&mut self, value: u16) {
+        let old_input = self.input();
+        self.counter = value;
+
+        if old_input && !self.input() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (result, did_overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+
+        if did_overflow {
+            self.overflow_pending = true;
+        }
+    }
+}
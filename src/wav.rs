@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// the riff header, fmt chunk and data chunk header all come before the samples
+const HEADER_SIZE: u32 = 44;
+
+// writes 16 bit pcm wav files
+//
+// the sizes in the header are kept up to date as samples are written,
+// so the file can be played even if the emulator never gets to finish it
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    // bytes of samples written so far
+    data_size: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+    ) -> io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate, channels)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32, channels: u16) -> io::Result<WavWriter<W>> {
+        let block_align = channels * 2;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // plain pcm
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            channels,
+            data_size: 0,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    // append samples from -1.0 to 1.0, interleaved if there's more than one channel
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+
+        self.update_sizes()
+    }
+
+    // make sure everything is on disk, and hand back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.update_sizes()?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    // rewrite the riff and data sizes, then carry on from the end
+    fn update_sizes(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+
+        Ok(())
+    }
+}
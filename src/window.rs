@@ -0,0 +1,454 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, Stream};
+use gilrs::{Axis, Gilrs};
+use minifb::{Key, KeyRepeat, WindowOptions};
+
+use crate::emulator::Emulator;
+use crate::frontend::{FramePacer, BUTTONS, PALETTE};
+use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::joypad::Button;
+
+const TITLE: &str = "gameboy-emulator";
+
+// how many times the real speed the game runs at while fast forwarding
+const FAST_FORWARD_SPEED: u32 = 4;
+
+// how much audio can be waiting to be played, in seconds, before more is thrown away
+//
+// the sound card's clock never quite matches the frame pacing, so the queue is kept short
+// rather than letting the sound drift further and further behind the picture
+const MAX_QUEUED_SECONDS: f32 = 0.1;
+
+// how far a stick has to be pushed before it counts as the d-pad
+const STICK_DEAD_ZONE: f32 = 0.5;
+
+// what a key can be bound to
+#[derive(Copy, Clone, PartialEq)]
+pub enum Action {
+    Button(Button),
+    Pause,
+    Reset,
+    FastForward,
+    Quit,
+}
+
+const ACTION_NAMES: [(&str, Action); 12] = [
+    ("up", Action::Button(Button::Up)),
+    ("down", Action::Button(Button::Down)),
+    ("left", Action::Button(Button::Left)),
+    ("right", Action::Button(Button::Right)),
+    ("a", Action::Button(Button::A)),
+    ("b", Action::Button(Button::B)),
+    ("select", Action::Button(Button::Select)),
+    ("start", Action::Button(Button::Start)),
+    ("pause", Action::Pause),
+    ("reset", Action::Reset),
+    ("fast-forward", Action::FastForward),
+    ("quit", Action::Quit),
+];
+
+const KEY_NAMES: [(&str, Key); 55] = [
+    ("a", Key::A),
+    ("b", Key::B),
+    ("c", Key::C),
+    ("d", Key::D),
+    ("e", Key::E),
+    ("f", Key::F),
+    ("g", Key::G),
+    ("h", Key::H),
+    ("i", Key::I),
+    ("j", Key::J),
+    ("k", Key::K),
+    ("l", Key::L),
+    ("m", Key::M),
+    ("n", Key::N),
+    ("o", Key::O),
+    ("p", Key::P),
+    ("q", Key::Q),
+    ("r", Key::R),
+    ("s", Key::S),
+    ("t", Key::T),
+    ("u", Key::U),
+    ("v", Key::V),
+    ("w", Key::W),
+    ("x", Key::X),
+    ("y", Key::Y),
+    ("z", Key::Z),
+    ("0", Key::Key0),
+    ("1", Key::Key1),
+    ("2", Key::Key2),
+    ("3", Key::Key3),
+    ("4", Key::Key4),
+    ("5", Key::Key5),
+    ("6", Key::Key6),
+    ("7", Key::Key7),
+    ("8", Key::Key8),
+    ("9", Key::Key9),
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("enter", Key::Enter),
+    ("space", Key::Space),
+    ("backspace", Key::Backspace),
+    ("tab", Key::Tab),
+    ("escape", Key::Escape),
+    ("left-shift", Key::LeftShift),
+    ("right-shift", Key::RightShift),
+    ("left-ctrl", Key::LeftCtrl),
+    ("right-ctrl", Key::RightCtrl),
+    ("left-alt", Key::LeftAlt),
+    ("right-alt", Key::RightAlt),
+    ("comma", Key::Comma),
+    ("period", Key::Period),
+    ("slash", Key::Slash),
+    ("semicolon", Key::Semicolon),
+];
+
+// which keys do what
+//
+// by default the arrow keys are the d-pad, x and z are A and B, enter is start and
+// backspace is select, with p to pause, r to reset, tab held to fast forward and escape to quit
+pub struct KeyMap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl KeyMap {
+    pub fn new() -> KeyMap {
+        KeyMap {
+            bindings: vec![
+                (Key::Up, Action::Button(Button::Up)),
+                (Key::Down, Action::Button(Button::Down)),
+                (Key::Left, Action::Button(Button::Left)),
+                (Key::Right, Action::Button(Button::Right)),
+                (Key::X, Action::Button(Button::A)),
+                (Key::Z, Action::Button(Button::B)),
+                (Key::Backspace, Action::Button(Button::Select)),
+                (Key::Enter, Action::Button(Button::Start)),
+                (Key::P, Action::Pause),
+                (Key::R, Action::Reset),
+                (Key::Tab, Action::FastForward),
+                (Key::Escape, Action::Quit),
+            ],
+        }
+    }
+
+    // the default keys, with some of them changed by a list like "a=k,b=j,start=space"
+    //
+    // an action given more than once is bound to all of its keys,
+    // returns none if an action or key isn't known
+    pub fn parse(spec: &str) -> Option<KeyMap> {
+        let mut changed = Vec::new();
+
+        for binding in spec.split(',').filter(|binding| !binding.is_empty()) {
+            let (action, key) = binding.split_once('=')?;
+            let action = lookup(&ACTION_NAMES, action)?;
+            let key = lookup(&KEY_NAMES, key)?;
+
+            changed.push((key, action));
+        }
+
+        let mut key_map = KeyMap::new();
+        key_map
+            .bindings
+            .retain(|(key, action)| !changed.iter().any(|(k, a)| k == key || a == action));
+        key_map.bindings.extend(changed);
+
+        Some(key_map)
+    }
+
+    fn is_down(&self, window: &minifb::Window, action: Action) -> bool {
+        self.keys(action).any(|key| window.is_key_down(key))
+    }
+
+    fn was_pressed(&self, window: &minifb::Window, action: Action) -> bool {
+        self.keys(action).any(|key| window.is_key_pressed(key, KeyRepeat::No))
+    }
+
+    fn keys(&self, action: Action) -> impl Iterator<Item = Key> + '_ {
+        self.bindings.iter().filter(move |(_, a)| *a == action).map(|&(key, _)| key)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::new()
+    }
+}
+
+// what the front end wants done next
+#[derive(Copy, Clone, PartialEq)]
+pub enum Control {
+    Run,
+    // don't run the game, but keep drawing and reading input so it can be unpaused
+    Paused,
+    // start the game again from scratch
+    Reset,
+    Quit,
+}
+
+// a front end that draws into a desktop window, scaled up by a whole number so pixels stay square
+//
+// keys are read through the key map, and any gamepads are read too, with the d-pad or left
+// stick as the d-pad, the right face button as A, the bottom one as B, and the right
+// shoulder button held to fast forward
+//
+// sound goes to the default output device, if there is one that takes float samples
+pub struct Window {
+    window: minifb::Window,
+    key_map: KeyMap,
+    // none if gamepads can't be read on this system
+    gamepads: Option<Gilrs>,
+    audio: Option<Audio>,
+    scale: usize,
+    // the scaled up frame, as 0RGB
+    buffer: Vec<u32>,
+    paused: bool,
+    fast_forward: bool,
+    pacer: FramePacer,
+}
+
+impl Window {
+    pub fn new(scale: usize, key_map: KeyMap) -> io::Result<Window> {
+        if scale == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "scale must be at least 1"));
+        }
+
+        let width = SCREEN_WIDTH * scale;
+        let height = SCREEN_HEIGHT * scale;
+
+        let mut window = minifb::Window::new(TITLE, width, height, WindowOptions::default())
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        // the frames are paced here, not by minifb
+        window.set_target_fps(0);
+
+        let gamepads = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(error) => {
+                eprintln!("gamepads won't work: {}", error);
+                None
+            }
+        };
+
+        // a window without sound is still worth having
+        let audio = match Audio::open() {
+            Ok(audio) => Some(audio),
+            Err(error) => {
+                eprintln!("sound won't play: {}", error);
+                None
+            }
+        };
+
+        Ok(Window {
+            window,
+            key_map,
+            gamepads,
+            audio,
+            scale,
+            buffer: vec![0; width * height],
+            paused: false,
+            fast_forward: false,
+            pacer: FramePacer::new(),
+        })
+    }
+
+    // the rate the emulator should produce samples at for play_samples,
+    // or none if there's nothing to play them on
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.audio.as_ref().map(|audio| audio.sample_rate)
+    }
+
+    // pass on the buttons held down to the emulator, and deal with the hotkeys
+    pub fn update_input(&mut self, emulator: &mut Emulator) -> Control {
+        if !self.window.is_open() || self.key_map.was_pressed(&self.window, Action::Quit) {
+            return Control::Quit;
+        }
+
+        if self.key_map.was_pressed(&self.window, Action::Pause) {
+            self.paused = !self.paused;
+            let title = if self.paused { format!("{} (paused)", TITLE) } else { TITLE.to_string() };
+            self.window.set_title(&title);
+        }
+
+        let mut held = [false; 8];
+        for (i, &button) in BUTTONS.iter().enumerate() {
+            held[i] = self.key_map.is_down(&self.window, Action::Button(button));
+        }
+        self.fast_forward = self.key_map.is_down(&self.window, Action::FastForward);
+
+        if let Some(gilrs) = &mut self.gamepads {
+            // the state of each gamepad is only brought up to date by going through its events
+            while gilrs.next_event().is_some() {}
+
+            for (_, gamepad) in gilrs.gamepads() {
+                let x = gamepad.value(Axis::LeftStickX);
+                let y = gamepad.value(Axis::LeftStickY);
+                let pressed = [
+                    gamepad.is_pressed(gilrs::Button::DPadRight) || x > STICK_DEAD_ZONE,
+                    gamepad.is_pressed(gilrs::Button::DPadLeft) || x < -STICK_DEAD_ZONE,
+                    gamepad.is_pressed(gilrs::Button::DPadUp) || y > STICK_DEAD_ZONE,
+                    gamepad.is_pressed(gilrs::Button::DPadDown) || y < -STICK_DEAD_ZONE,
+                    gamepad.is_pressed(gilrs::Button::East),
+                    gamepad.is_pressed(gilrs::Button::South),
+                    gamepad.is_pressed(gilrs::Button::Select),
+                    gamepad.is_pressed(gilrs::Button::Start),
+                ];
+
+                for (held, pressed) in held.iter_mut().zip(pressed.iter()) {
+                    *held |= pressed;
+                }
+                self.fast_forward |= gamepad.is_pressed(gilrs::Button::RightTrigger);
+            }
+        }
+
+        for (&button, &held) in BUTTONS.iter().zip(held.iter()) {
+            emulator.set_button(button, held);
+        }
+
+        // checked last, so the buttons are still passed on to the fresh emulator
+        if self.key_map.was_pressed(&self.window, Action::Reset) {
+            self.paused = false;
+            self.window.set_title(TITLE);
+            return Control::Reset;
+        }
+
+        if self.paused {
+            Control::Paused
+        } else {
+            Control::Run
+        }
+    }
+
+    // draw a frame of shades, as given by Emulator::framebuffer
+    //
+    // this is also when the window picks up new key presses, so it has to be done every frame
+    pub fn draw(&mut self, framebuffer: &[u8]) -> io::Result<()> {
+        let width = SCREEN_WIDTH * self.scale;
+        // minifb takes pixels as 0RGB
+        let colours = PALETTE.map(|(r, g, b)| (r as u32) << 16 | (g as u32) << 8 | b as u32);
+
+        for (y, row) in framebuffer.chunks(SCREEN_WIDTH).enumerate() {
+            let start = y * self.scale * width;
+
+            // scale up one line, then copy it down for the rest of the pixel's height
+            for (x, &shade) in row.iter().enumerate() {
+                let pixel = start + x * self.scale;
+                self.buffer[pixel..pixel + self.scale].fill(colours[shade as usize]);
+            }
+            for line in 1..self.scale {
+                self.buffer.copy_within(start..start + width, start + line * width);
+            }
+        }
+
+        self.window
+            .update_with_buffer(&self.buffer, width, SCREEN_HEIGHT * self.scale)
+            .map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    // queue interleaved stereo samples, as given by Emulator::take_samples, to be played
+    pub fn play_samples(&mut self, samples: &[f32]) {
+        // sped up audio in bits and pieces is worse than none at all
+        if self.fast_forward || self.paused {
+            return;
+        }
+
+        if let Some(audio) = &self.audio {
+            audio.queue(samples);
+        }
+    }
+
+    // wait for the next frame, which comes sooner while fast forwarding
+    pub fn wait_for_next_frame(&mut self) {
+        let speed = if self.fast_forward && !self.paused { FAST_FORWARD_SPEED } else { 1 };
+        self.pacer.wait(speed);
+    }
+}
+
+// the sound card, which pulls samples off a queue from its own thread
+struct Audio {
+    // kept alive for as long as the sound should play
+    _stream: Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl Audio {
+    fn open() -> Result<Audio, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no output device")?;
+
+        // the default config is best, but only float samples are handled
+        let default = device.default_output_config().map_err(|error| error.to_string())?;
+        let supported = if default.sample_format() == SampleFormat::F32 {
+            default
+        } else {
+            device
+                .supported_output_configs()
+                .map_err(|error| error.to_string())?
+                .find(|config| config.sample_format() == SampleFormat::F32)
+                .ok_or("the output device doesn't take float samples")?
+                .with_max_sample_rate()
+        };
+
+        let config = supported.config();
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let source = Arc::clone(&queue);
+
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |output: &mut [f32], _| {
+                    let mut queue = source.lock().unwrap();
+
+                    for frame in output.chunks_mut(channels) {
+                        // running dry just means silence until the next frame's samples arrive
+                        let left = queue.pop_front().unwrap_or(0.0);
+                        let right = queue.pop_front().unwrap_or(0.0);
+
+                        if channels == 1 {
+                            frame[0] = (left + right) / 2.0;
+                        } else {
+                            frame[0] = left;
+                            frame[1] = right;
+                            frame[2..].fill(0.0);
+                        }
+                    }
+                },
+                |error| eprintln!("sound error: {}", error),
+                None,
+            )
+            .map_err(|error| error.to_string())?;
+        stream.play().map_err(|error| error.to_string())?;
+
+        Ok(Audio {
+            _stream: stream,
+            queue,
+            sample_rate,
+        })
+    }
+
+    fn queue(&self, samples: &[f32]) {
+        let max = (self.sample_rate as f32 * MAX_QUEUED_SECONDS) as usize * 2;
+        let mut queue = self.queue.lock().unwrap();
+
+        queue.extend(samples);
+        // throw away whole stereo samples from the front, so left and right stay in order
+        if queue.len() > max {
+            let excess = (queue.len() - max) & !0x1;
+            queue.drain(..excess);
+        }
+    }
+}
+
+fn lookup<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    let name = name.trim().to_lowercase();
+    names.iter().find(|(n, _)| *n == name).map(|&(_, value)| value)
+}